@@ -1,21 +1,29 @@
 pub mod parse;
+pub mod replace;
 
 use std::io;
 use std::io::{stdout, Write, Read};
 use std::option;
-use std::fs::File;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
 use std::process::exit;
+use std::collections::{HashSet, HashMap};
 
 use termion::{color, style};
-use lazysort::{Sorted, SortedBy};
-use regex::Regex;
+use lazysort::Sorted;
+use regex::{Regex, RegexBuilder, Replacer};
 use regex;
 
-use readline::read_line;
+use readline::{read_line, read_line_initial, can_prefill, start_recording, stop_recording, history};
 use red_master::RedMaster;
-use red_buffer::RedBuffer;
-use range::Range;
+use red_buffer::{RedBuffer, Mark};
+use red_buffer::format::FileFormat;
+use range::{Range, spanned_lines};
 use config;
+use editorconfig;
+use command::{run_lines, source_file, exit_status};
+use self::replace::Replacement;
 
 static SEL_CHARS: &str =
     "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ!\"#%&'()*+,-./:<;=@>?[\\]^_`{|}";
@@ -24,6 +32,7 @@ static SEL_CHARS: &str =
 pub enum Action {
     Insert,  // Inserts text before a line
     Change,  // Change the content of a line
+    ChangeText(ChangeTarget, String), // Change part of every line without prompting
     Append,  // Append text at the end of a line
     Delete(String),  // Optionally puts into a registers
     Yank(String),
@@ -40,20 +49,52 @@ pub enum Action {
     Clear, // Clear the screen
 
     CopyTo(Range),   // Copy a range from one place to another
-    Substitute(String, String), // Substitute a by b
+    Substitute(String, Replacement, SubstituteFlags), // Substitute a by b
 
     SetMark(String),
 
+    Global(bool, String, String), // Run a command on every line (not) matching a regex
+
+    Record(Option<String>), // Start recording a macro into a register, or stop recording
+    Replay(String, Option<usize>), // Replay a macro n times, or once for every line in the range
+
     Print,   // Print a range with line number
     Print_,   // Print a line
 
     BufList, // List all buffers
-    BufChange(usize), // Change buffer
+    BufChange(usize), // Change buffer by ID
+    BufFind(String), // Change to the buffer whose file name contains this
+    BufAlternate, // Change to the previously used buffer
     BufNew(Option<String>), // New buffer
     BufDel(bool), // Delete buffer (force)
+    BufDo(String), // Run a command in every buffer
 
     Write(String),
-    Edit(bool, String)
+    WriteAll, // Write every modified buffer
+    WriteQuit(String), // Write the buffer, then close it
+    QuitAll(bool), // Quit, unless a buffer isn't saved (force)
+    Edit(bool, String),
+    View(bool, String), // Edit a file read-only
+
+    Source(String), // Run the commands in a file
+    Set(String, Option<String>), // Set an option
+    ShowOptions(Option<String>), // Show the value of all or any option
+    History(Option<usize>), // List the last n commands, or all of them
+}
+
+#[derive(Debug, Clone)]
+pub enum ChangeTarget {
+    Columns(usize, usize), // Start and end column, both inclusive
+    Match(String, usize),  // The n-th match of a regex, starting at 1
+}
+
+#[derive(Debug, Clone)]
+pub struct SubstituteFlags {
+    pub global: bool,           // Replace every match from the n-th one and onwards
+    pub nth: usize,             // Which match to replace, starting at 1
+    pub case_insensitive: bool,
+    pub confirm: bool,          // Ask before replacing each match
+    pub multi_line: bool,       // Match across the lines of the range
 }
 
 #[derive(Debug)]
@@ -63,11 +104,30 @@ pub enum ActionErr {
     IO(io::Error),
     NoSuchRegisters,
     Regex,
+    Parse(String), // A command couldn't be parsed, with a description of why
+    NoMatch,       // A search or a regex didn't match anything
+    ReadOnly,      // The buffer can't be modified
     Other,
 }
 
+impl ActionErr {
+    // The status red exits with when a script fails because of this
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            ActionErr::Parse(_) => 2,
+            ActionErr::IO(_) => 3,
+            ActionErr::NoMatch => 4,
+            _ => 1,
+        }
+    }
+}
+
 impl Action {
     pub fn apply(self, master: &mut RedMaster) -> Result<(), ActionErr> {
+        if self.modifies() && master.curr_buf().read_only {
+            return Err(ActionErr::ReadOnly);
+        }
+
         let modified = match self {
             Action::Delete(reg) => {
                 let removed_lines = {
@@ -95,29 +155,15 @@ impl Action {
             }
             Action::Insert => {
                 let file = master.curr_buf_mut();
-                let mut modified = false;
-                loop {
-                    let to_insert = read_line("i> ");
-                    if let Err(_) = to_insert {
-                        break;
-                    }
-                    let to_insert = to_insert.unwrap();
-                    if to_insert == "." {
-                        break;
-                    }
-                    for line in file.cursor.lines.clone().into_iter().sorted_by(|x, y| y.cmp(x)) {
-                        file.insert_line(line, to_insert.clone())?;
-                    }
-                    modified = true;
-                }
-
-                modified
+                let points = file.cursor.lines.clone().into_iter().sorted().collect();
+                insert_text(file, points, "i> ", true)?
             }
             Action::Indent => {
                 let mut file = master.curr_buf_mut();
+                let unit = file.indent_unit();
                 for line in file.cursor.lines.clone() {
                     if let Some(ref mut li) = file.lines.get_mut(line) {
-                        li.insert_str(0, "    ");
+                        li.insert_str(0, &unit);
                     } else {
                         return Err(ActionErr::OutOfBounds);
                     }
@@ -126,11 +172,16 @@ impl Action {
             }
             Action::Unindent => {
                 let mut file = master.curr_buf_mut();
+                let width = file.indent_width();
                 for line in file.cursor.lines.clone() {
                     if let Some(ref mut li) = file.lines.get_mut(line) {
-                        for _ in 0..4 {
-                            if Some(' ') == li.chars().nth(0) {
-                                li.remove(0);
+                        if li.starts_with('\t') {
+                            li.remove(0);
+                        } else {
+                            for _ in 0..width {
+                                if Some(' ') == li.chars().nth(0) {
+                                    li.remove(0);
+                                }
                             }
                         }
                     } else {
@@ -141,36 +192,31 @@ impl Action {
             }
             Action::Append => {
                 let file = master.curr_buf_mut();
-                let mut modified = false;
-                let mut i = 1;
-                loop {
-                    let to_insert = read_line("a> ");
-                    if let Err(_) = to_insert {
-                        break;
-                    }
-                    let to_insert = to_insert.unwrap();
-
-                    if to_insert == "." {
-                        break;
-                    }
-                    for line in file.cursor.lines.clone().into_iter().sorted_by(|x, y| y.cmp(x)) {
-                        file.insert_line(line + i, to_insert.clone())?;
-                    }
-                    i += 1;
-                    modified = true;
-                }
-                modified
+                let points = file.cursor.lines.clone().into_iter().sorted().map(|l| l + 1).collect();
+                insert_text(file, points, "a> ", false)?
             }
             Action::Change => {
                 let file = master.curr_buf_mut();
                 'outer: for line in file.cursor.lines.clone().into_iter().sorted() {
                     let content = file.lines[line].clone();
+
+                    if can_prefill() {
+                        match read_line_initial("c> ", &content) {
+                            Ok(text) => {
+                                file.lines[line] = text;
+                                continue;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+
+                    // Scripted input can't edit a prefilled line, pick targets instead
                     let mut sel_chars = SEL_CHARS.to_string();
                     sel_chars.truncate(content.len());
                     sel_chars += "$";
 
                     println!("  {}", content);
-                    println!("{}  {}{}", color::Fg(color::Cyan), sel_chars, style::Reset);
+                    println!("{}  {}{}", fg(color::Cyan), sel_chars, reset());
 
                     let mut targets;
                     loop {
@@ -199,16 +245,47 @@ impl Action {
                     let text = text.unwrap();
 
 
-                    let line_before = file.lines[line].clone();
-                    file.lines[line] = line_before[..start].to_string();
-                    file.lines[line].push_str(&text);
-                    if end < content.len() {
-                        file.lines[line].push_str(&line_before[end..]);
-                    }
+                    file.lines[line] = change_span(&content, start, end, &text).ok_or(ActionErr::OutOfBounds)?;
                 }
 
                 true
             }
+            Action::ChangeText(target, text) => {
+                if let ChangeTarget::Columns(start, end) = target {
+                    if end < start {
+                        return Err(ActionErr::OutOfBounds);
+                    }
+                }
+                let file = master.curr_buf_mut();
+                let re = match target {
+                    ChangeTarget::Match(ref pat, _) => Some(Regex::new(pat)?),
+                    ChangeTarget::Columns(..) => None,
+                };
+                let mut modified = false;
+                let mut found = false;
+
+                for line in file.cursor.lines.clone().into_iter().sorted() {
+                    let content = file.lines.get(line).ok_or(ActionErr::OutOfBounds)?.clone();
+                    let span = match (&target, &re) {
+                        (&ChangeTarget::Columns(start, end), _) => Some((start, end.saturating_add(1))),
+                        (&ChangeTarget::Match(_, n), &Some(ref re)) =>
+                            re.find_iter(&content).nth(n.saturating_sub(1)).map(|m| (m.start(), m.end())),
+                        _ => None,
+                    };
+
+                    // Lines too short for the columns are left unchanged
+                    if let Some(changed) = span.and_then(|(start, end)| change_span(&content, start, end, &text)) {
+                        file.lines[line] = changed;
+                        modified = true;
+                        found = true;
+                    }
+                }
+
+                if re.is_some() && !found {
+                    return Err(ActionErr::NoMatch);
+                }
+                modified
+            }
             Action::AppendText(text) => {
                 let file = master.curr_buf_mut();
 
@@ -312,20 +389,66 @@ impl Action {
                 }
                 true
             }
-            Action::Substitute(pat, rep) => {
+            Action::Substitute(pat, mut rep, flags) => {
                 let file = master.curr_buf_mut();
-                let replacer: &str = &rep;
-                let rpat = Regex::new(&pat)?;
+                let multi_line = flags.multi_line || pat.contains("\\n");
+                let rpat = RegexBuilder::new(&pat)
+                    .case_insensitive(flags.case_insensitive)
+                    .multi_line(multi_line)
+                    .build()?;
                 let mut count = 0;
                 let mut lines = 0;
-                for i in file.cursor.lines.iter() {
-                    let line = file.lines[*i].clone();
-                    let matches_on_line = rpat.find_iter(&line).count();
-                    count += matches_on_line;
-                    lines += if matches_on_line > 0 { 1 } else { 0 };
-                    let replaced = rpat.replace_all(&line, replacer).into_owned();
+                let mut found = false;
+                // Last answer given in confirm mode, 'a' and 'q' apply to all remaining matches
+                let mut answer = if flags.confirm { 'y' } else { 'a' };
+
+                // In multi-line mode, every continuous part of the range is substituted as one text
+                let mut blocks: Vec<(usize, usize)> = Vec::new();
+                for i in file.cursor.lines.clone().into_iter().sorted() {
+                    match blocks.last_mut() {
+                        Some(&mut (_, ref mut end)) if multi_line && *end + 1 == i => *end = i,
+                        _ => blocks.push((i, i)),
+                    }
+                }
+
+                // Number of lines added by earlier replacements
+                let mut shift: isize = 0;
+
+                for (start, end) in blocks {
+                    let start = (start as isize + shift) as usize;
+                    let end = (end as isize + shift) as usize;
+                    let text = file.lines.get(start..end + 1).ok_or(ActionErr::OutOfBounds)?.join("\n");
 
-                    file.lines[*i] = replaced;
+                    let mut touched = HashSet::new();
+                    let mut line_nr = start;
+                    let mut pos = 0;
+
+                    let (replaced, replacements) =
+                        substitute_text(&rpat, &text, &mut rep, &flags, |m_start, m_end| {
+                            line_nr += text[pos..m_start].matches('\n').count();
+                            pos = m_start;
+                            found = true;
+
+                            if answer != 'a' && answer != 'q' {
+                                answer = confirm_replace(line_nr, &text, m_start, m_end);
+                            }
+                            let accepted = answer == 'y' || answer == 'a';
+                            if accepted {
+                                touched.extend(line_nr..line_nr + spanned_lines(&text[m_start..m_end]));
+                            }
+                            accepted
+                        });
+                    count += replacements;
+                    lines += touched.len();
+
+                    if replacements > 0 {
+                        let new_lines: Vec<String> = replaced.split('\n').map(|l| l.to_string()).collect();
+                        shift += new_lines.len() as isize - (end + 1 - start) as isize;
+                        file.replace_lines(start, end + 1, new_lines)?;
+                    }
+                }
+                if !found {
+                    return Err(ActionErr::NoMatch);
                 }
                 if !config::CONF.lock().unwrap().silent {
                     println!("Did {} replacements on {} lines", count, lines);
@@ -337,26 +460,96 @@ impl Action {
                 file.marks.insert(mark.into(), file.cursor.clone());
                 true
             }
+            Action::Global(invert, pat, command) => {
+                let re = Regex::new(&pat)?;
+                let targets: HashSet<usize> = {
+                    let file = master.curr_buf();
+                    file.cursor.lines.iter()
+                        .filter(|&&l| file.lines.get(l).map(|line| re.is_match(line) != invert).unwrap_or(false))
+                        .cloned()
+                        .collect()
+                };
+
+                if targets.is_empty() {
+                    return Err(ActionErr::NoMatch);
+                }
+                // The command finding nothing on some of the lines doesn't stop it from running on the others
+                for_each_line(master, targets, |master| {
+                    match run_lines(vec![command.clone()], master) {
+                        Err(ActionErr::NoMatch) => Ok(()),
+                        res => res,
+                    }
+                })?;
+                false
+            }
+            Action::Record(Some(reg)) => {
+                if master.recording.is_some() {
+                    eprintln!("Already recording!");
+                    return Err(ActionErr::Other);
+                }
+                start_recording();
+                master.recording = Some(reg.into());
+                false
+            }
+            Action::Record(None) => {
+                if let (Some(reg), Some(mut lines)) = (master.recording.take(), stop_recording()) {
+                    lines.pop(); // The line stopping the recording
+                    if !config::CONF.lock().unwrap().silent {
+                        println!("Recorded {} lines into {}", lines.len(), &*reg);
+                    }
+                    master.registers.insert(reg, lines);
+                } else {
+                    eprintln!("Not recording!");
+                    return Err(ActionErr::Other);
+                }
+                false
+            }
+            Action::Replay(reg, times) => {
+                let lines = match master.registers.get(&reg.into()) {
+                    Some(lines) => lines.clone(),
+                    None => return Err(ActionErr::NoSuchRegisters),
+                };
+
+                match times {
+                    Some(times) => {
+                        for _ in 0..times {
+                            run_lines(lines.clone(), master)?;
+                        }
+                    }
+                    None => {
+                        let targets = master.curr_buf().cursor.lines.clone();
+                        for_each_line(master, targets, |master| run_lines(lines.clone(), master))?;
+                    }
+                }
+                false
+            }
             Action::BufList => {
-                for (i, buf) in master.buffers.iter().enumerate() {
+                let alternate = master.alternate_buffer();
+                for i in master.recent_buffers() {
+                    let buf = &master.buffers[i];
                     if &i == master.curr_buf_idx() {
                         print!("* ");
+                    } else if Some(i) == alternate {
+                        print!("# ");
                     } else {
                         print!("  ");
                     }
-                    print!("{}{}: ", color::Fg(color::Cyan), i);
+                    print!("{}{}: ", fg(color::Cyan), buf.id);
                     match buf.filename {
                         Some(ref name) => {
-                            print!("{}{}", color::Fg(color::Green), name);
+                            print!("{}{}", fg(color::Green), name);
                         }
                         None => {
-                            print!("{}[untitled]", color::Fg(color::Green));
+                            print!("{}[untitled]", fg(color::Green));
                         }
                     }
                     if !buf.saved {
                         print!(" [+]");
                     }
-                    println!("{}", style::Reset);
+                    if buf.read_only {
+                        print!(" [RO]");
+                    }
+                    println!("{}", reset());
                 }
                 false
             }
@@ -366,7 +559,7 @@ impl Action {
                     return Err(ActionErr::Other);
                 }
                 if master.buffers.len() == 1 {
-                    exit(0);
+                    exit(exit_status());
                 }
                 if let Some(ref name) = master.curr_buf().filename {
                     println!("Closing {}", name);
@@ -374,17 +567,11 @@ impl Action {
                     println!("Closing [untitled]");
                 }
 
-                let idx = master.curr_buf_idx().clone();
-                master.buffers.remove(idx);
-                if idx > 0 {
-                    master.change_buffer(idx - 1)?;
-                }
+                master.remove_buffer()?;
                 false
             }
             Action::BufNew(None) => {
-                master.buffers.push(RedBuffer::empty());
-                let buffers = master.buffers.len();
-                master.change_buffer(buffers - 1)?;
+                master.add_buffer(RedBuffer::empty())?;
 
                 if !config::CONF.lock().unwrap().silent {
                     println!("Editing new file!");
@@ -393,85 +580,239 @@ impl Action {
                 false
             }
             Action::BufNew(Some(file_name)) => {
-                master.buffers.push(RedBuffer::empty());
-                let buffers = master.buffers.len();
-                master.change_buffer(buffers - 1)?;
+                master.add_buffer(RedBuffer::empty())?;
 
                 Action::Edit(true, file_name).apply(master)?;
                 false
             }
-            Action::BufChange(i) => {
-                master.change_buffer(i)?;
+            Action::BufChange(id) => {
+                let idx = master.find_buffer(id).ok_or(ActionErr::OutOfBounds)?;
+                master.switch_buffer(idx)?;
+                false
+            }
+            Action::BufFind(name) => {
+                let idx = find_buffer_by_name(master, name.trim())?;
+                master.switch_buffer(idx)?;
+                false
+            }
+            Action::BufAlternate => {
+                match master.alternate_buffer() {
+                    Some(idx) => master.switch_buffer(idx)?,
+                    None => {
+                        eprintln!("No alternate buffer!");
+                        return Err(ActionErr::Other);
+                    }
+                }
+                false
+            }
+            Action::BufDo(command) => {
+                let original = master.curr_buf().id;
+                // Only the buffers open now, the command may open and close others
+                let ids: Vec<usize> = master.buffers.iter().map(|buf| buf.id).collect();
+                for id in ids {
+                    let idx = match master.find_buffer(id) {
+                        Some(idx) => idx,
+                        None => continue,
+                    };
+                    master.change_buffer(idx)?;
+                    match run_lines(vec![command.clone()], master) {
+                        Ok(()) | Err(ActionErr::NoMatch) => { }
+                        Err(e) => return Err(e),
+                    }
+                }
+                // Back to where it started, which is used more recently than anything the command opened
+                if let Some(idx) = master.find_buffer(original) {
+                    master.switch_buffer(idx)?;
+                }
                 false
             }
             Action::Write(n_path) => {
                 let file = master.curr_buf_mut();
+                // Saving a copy somewhere else is fine
+                let same_file = n_path.trim().is_empty() || file.filename.as_ref().map(|f| is_same_file(f, &n_path)).unwrap_or(false);
+                if file.read_only && same_file {
+                    return Err(ActionErr::ReadOnly);
+                }
                 let path =
                     if n_path.trim().is_empty() {
                         file.clone().filename?
                     } else { n_path };
-                let mut out = File::create(path.trim())?;
-                let mut first = true;
-                for line in file.lines.iter() {
-                    if !first {
-                        out.write(&[10])?;
+                if config::CONF.lock().unwrap().backup && Path::new(path.trim()).is_file() {
+                    fs::copy(path.trim(), format!("{}~", path.trim()))?;
+                }
+                if file.format.trim_trailing_whitespace {
+                    for line in file.lines.iter_mut() {
+                        let trimmed = line.trim_end().len();
+                        line.truncate(trimmed);
                     }
-                    first = false;
-                    out.write(line.bytes().collect::<Vec<u8>>().as_slice())?;
+                }
+                let content = file.format.write(&file.lines)?;
+                let mut out = File::create(path.trim())?;
+                out.write_all(&content)?;
+                // The copy is a file of its own, which isn't read-only
+                if !same_file {
+                    file.read_only = false;
                 }
                 file.filename = Some(path);
                 file.saved = true;
 
                 false
             }
+            Action::WriteAll => {
+                let original = *master.curr_buf_idx();
+                for i in 0..master.buffers.len() {
+                    if master.buffers[i].saved {
+                        continue;
+                    }
+                    if master.buffers[i].filename.is_none() {
+                        eprintln!("Buffer {} has no file to write to!", master.buffers[i].id);
+                        return Err(ActionErr::Other);
+                    }
+                    master.change_buffer(i)?;
+                    let res = Action::Write(String::new()).apply(master);
+                    master.change_buffer(original)?;
+                    res?;
+                }
+                false
+            }
+            Action::WriteQuit(path) => {
+                Action::Write(path).apply(master)?;
+                Action::BufDel(false).apply(master)?;
+                false
+            }
+            Action::QuitAll(force) => {
+                let unsaved = master.unsaved_buffers();
+                if !unsaved.is_empty() && !force {
+                    eprintln!("Not saved: {}", unsaved.join(", "));
+                    return Err(ActionErr::Other);
+                }
+                exit(exit_status());
+            }
             Action::Edit(force, path) => {
                 let file = master.curr_buf_mut();
                 if !file.saved && !force {
                     eprintln!("Not saved!");
                     return Err(ActionErr::Other);
                 }
+                let props =
+                    if config::CONF.lock().unwrap().editorconfig {
+                        editorconfig::properties(path.trim())
+                    } else {
+                        HashMap::new()
+                    };
+
+                file.indent = None;
+                file.format = FileFormat::new();
+                if let Some(charset) = editorconfig::charset(&props) {
+                    file.format.charset = charset;
+                }
+
                 let mut f = File::open(path.trim());
                 if f.is_ok() {
                     let mut f = f.unwrap();
-                    let mut content = String::new();
-                    f.read_to_string(&mut content)?;
+                    let mut content = Vec::new();
+                    f.read_to_end(&mut content)?;
 
-                    file.lines = content.lines().map(|x| x.to_string()).collect();
+                    file.lines = file.format.read(&content)?;
                     file.cursor = Range::empty();
+                    file.read_only = OpenOptions::new().write(true).open(path.trim()).is_err();
+                    if config::CONF.lock().unwrap().detect_indent {
+                        file.detect_indent();
+                    }
                     if !config::CONF.lock().unwrap().silent {
                         println!("Editing {} [{}]", path.trim(), file.lines.len());
                     }
                 } else {
                     file.lines = vec![];
                     file.cursor = Range::empty();
+                    file.read_only = false;
                     if !config::CONF.lock().unwrap().silent {
                         println!("Editing {} [NEW]", path.trim());
                     }
                 }
+                editorconfig::apply(&props, file);
                 file.saved = true;
                 file.filename = Some(path.trim().to_string());
 
                 false
             }
+            Action::View(force, path) => {
+                Action::Edit(force, path).apply(master)?;
+                master.curr_buf_mut().read_only = true;
+                false
+            }
+            Action::Source(path) => {
+                source_file(path.trim(), master)?;
+                false
+            }
+            // Being read-only is set for each buffer, unlike the other options
+            Action::Set(ref name, ref value) if name == "readonly" || name == "noreadonly" => {
+                let value = if name == "noreadonly" { Some("false") } else { value.as_ref().map(|v| &**v) };
+                match config::parse_bool(value) {
+                    Ok(read_only) => master.curr_buf_mut().read_only = read_only,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return Err(ActionErr::Other);
+                    }
+                }
+                false
+            }
+            Action::ShowOptions(Some(ref name)) if name == "readonly" => {
+                println!("readonly={}", master.curr_buf().read_only);
+                false
+            }
+            Action::Set(name, value) => {
+                if let Err(e) = config::CONF.lock().unwrap().set(&name, value.as_ref().map(|v| &**v)) {
+                    eprintln!("{}", e);
+                    return Err(ActionErr::Other);
+                }
+                false
+            }
+            Action::ShowOptions(Some(name)) => {
+                match config::CONF.lock().unwrap().get(&name) {
+                    Some(value) => println!("{}={}", name, value),
+                    None => {
+                        eprintln!("No such option: {}", name);
+                        return Err(ActionErr::Other);
+                    }
+                }
+                false
+            }
+            Action::ShowOptions(None) => {
+                for name in config::OPTIONS {
+                    Action::ShowOptions(Some(name.to_string())).apply(master)?;
+                }
+                Action::ShowOptions(Some("readonly".into())).apply(master)?;
+                false
+            }
+            Action::History(count) => {
+                let history = history();
+                let skip = history.len().saturating_sub(count.unwrap_or(history.len()));
+                let width = history.len().to_string().len();
+                for (i, cmd) in history.iter().enumerate().skip(skip) {
+                    println!("{3}{1:>0$}{2} {4}", width, i + 1, reset(), fg(color::Cyan), cmd);
+                }
+                false
+            }
             Action::Print => {
                 let file = master.curr_buf_mut();
                 let mut next = None;
                 let leading_digits = file.cursor.lines.clone().into_iter()
-                        .map(|x| ((x + 1) as f32).log10().floor() as usize)
+                        .map(|x| format_number(x).len())
                         .max()
-                        .unwrap_or(0) + 1;
+                        .unwrap_or(1);
 
                 for line in file.cursor.lines.clone().into_iter().sorted() {
                     if next.is_some() && Some(line) != next {
-                        println!("{}    ...", color::Fg(color::Green));
+                        println!("{}    ...", fg(color::Green));
                     }
                     match file.lines.get(line) {
                         Some(content) => {
-                            print!("{3}{1:0$}{2} ", leading_digits, line, style::Reset, color::Fg(color::Cyan));
+                            print!("{3}{1:>0$}{2} ", leading_digits, format_number(line), reset(), fg(color::Cyan));
                             println!("{}", content);
                         }
                         None => {
-                            println!("{3}{1:0$}{2}", leading_digits, line, style::Reset, color::Fg(color::Red));
+                            println!("{3}{1:>0$}{2}", leading_digits, format_number(line), reset(), fg(color::Red));
                         }
                     }
                     next = Some(line + 1);
@@ -492,6 +833,226 @@ impl Action {
         }
         Ok(())
     }
+
+    // Whether this changes the content of the buffer. Actions running other commands don't,
+    // those commands are checked themselves
+    fn modifies(&self) -> bool {
+        match *self {
+            Action::Insert | Action::Change | Action::ChangeText(..) | Action::Append |
+            Action::Delete(_) | Action::Paste(_) | Action::InsertText(_) | Action::AppendText(_) |
+            Action::Indent | Action::Unindent | Action::CopyTo(_) | Action::Substitute(..) => true,
+            _ => false,
+        }
+    }
+}
+
+// Reads lines until a single period, inserting each one at every point, after the lines inserted
+// before it. If inserting_before, the first line is inserted before the line it's indented after.
+// Returns whether anything was inserted
+fn insert_text(file: &mut RedBuffer, mut points: Vec<usize>, prompt: &str, inserting_before: bool) -> Result<bool, ActionErr> {
+    let (autoindent, smartindent) = {
+        let conf = config::CONF.lock().unwrap();
+        (conf.autoindent, conf.smartindent)
+    };
+    let unit = file.indent_unit();
+    let mut first = true;
+
+    loop {
+        let to_insert = read_line(prompt);
+        if let Err(_) = to_insert {
+            break;
+        }
+        let mut to_insert = to_insert.unwrap();
+        if file.format.trim_trailing_whitespace {
+            to_insert = to_insert.trim_end().to_string();
+        }
+        if to_insert == "." {
+            break;
+        }
+
+        let mut shift = 0;
+        for point in points.iter_mut() {
+            *point += shift;
+
+            let mut line = to_insert.clone();
+            if autoindent && line.starts_with('\\') {
+                // Literal line, without any indentation added
+                line.remove(0);
+            } else if autoindent && !line.is_empty() {
+                let anchor_below = first && inserting_before;
+                let indent = match anchor_line(&file.lines, *point, anchor_below) {
+                    Some(anchor) => {
+                        let mut indent: String = anchor.chars().take_while(|c| c.is_whitespace()).collect();
+                        let opens_block = anchor.trim_end().ends_with('{') || anchor.trim_end().ends_with(':');
+                        if smartindent && !anchor_below && opens_block {
+                            indent.push_str(&unit);
+                        }
+                        indent
+                    }
+                    None => String::new(),
+                };
+                line.insert_str(0, &indent);
+            }
+
+            file.insert_line(*point, line)?;
+            *point += 1;
+            shift += 1;
+        }
+        first = false;
+    }
+
+    Ok(!first)
+}
+
+// Whether the paths lead to the same file, if it exists
+fn is_same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a.trim()), fs::canonicalize(b.trim())) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.trim() == b.trim(),
+    }
+}
+
+// Finds the buffer whose file name is name, or else the only one containing it
+fn find_buffer_by_name(master: &RedMaster, name: &str) -> Result<usize, ActionErr> {
+    let names: Vec<Option<&str>> = master.buffers.iter().map(|buf| buf.filename.as_ref().map(|f| &**f)).collect();
+    if let Some(idx) = names.iter().position(|&f| f == Some(name)) {
+        return Ok(idx);
+    }
+    let matches: Vec<usize> = names.iter().enumerate()
+        .filter(|&(_, f)| f.map(|f| f.contains(name)).unwrap_or(false))
+        .map(|(i, _)| i)
+        .collect();
+    match matches.len() {
+        1 => Ok(matches[0]),
+        0 => {
+            eprintln!("No buffer matches {}!", name);
+            Err(ActionErr::Other)
+        }
+        _ => {
+            let found: Vec<String> = matches.iter()
+                .map(|&i| format!("{}: {}", master.buffers[i].id, names[i].unwrap()))
+                .collect();
+            eprintln!("{} is ambiguous, matches {}", name, found.join(", "));
+            Err(ActionErr::Other)
+        }
+    }
+}
+
+// The closest non-empty line before (or if below, at or after) the point where a line is inserted
+fn anchor_line(lines: &[String], point: usize, below: bool) -> Option<&String> {
+    if below {
+        lines.iter().skip(point).find(|l| !l.trim().is_empty())
+    } else {
+        lines.iter().take(point).rev().find(|l| !l.trim().is_empty())
+    }
+}
+
+// Colours and line numbers follow the colors and base options
+fn fg<C: color::Color>(c: C) -> String {
+    if config::CONF.lock().unwrap().colors { color::Fg(c).to_string() } else { String::new() }
+}
+
+fn bg<C: color::Color>(c: C) -> String {
+    if config::CONF.lock().unwrap().colors { color::Bg(c).to_string() } else { String::new() }
+}
+
+fn reset() -> String {
+    if config::CONF.lock().unwrap().colors { style::Reset.to_string() } else { String::new() }
+}
+
+fn format_number(n: usize) -> String {
+    config::CONF.lock().unwrap().format_number(n)
+}
+
+// Selects each line in targets from the top and runs f with it. The remaining lines are kept in
+// a mark, so they're moved along when f inserts or deletes lines
+fn for_each_line<F>(master: &mut RedMaster, targets: HashSet<usize>, mut f: F) -> Result<(), ActionErr>
+    where F: FnMut(&mut RedMaster) -> Result<(), ActionErr> {
+    let mark: Mark = {
+        let file = master.curr_buf_mut();
+        let depth = file.marks.keys().filter(|m| m.starts_with(" each")).count();
+        let mark: Mark = format!(" each{}", depth).into();
+        file.marks.insert(mark.clone(), Range { lines: targets });
+        mark
+    };
+
+    let res = loop {
+        let next = master.curr_buf().marks.get(&mark)
+            .and_then(|r| r.lines.iter().min().cloned());
+        let line = match next {
+            Some(line) => line,
+            None => break Ok(()),
+        };
+        {
+            let file = master.curr_buf_mut();
+            file.marks.get_mut(&mark).unwrap().lines.remove(&line);
+            file.cursor = Range::new_with_line(line);
+        }
+
+        if let Err(e) = f(master) {
+            break Err(e);
+        }
+    };
+    master.curr_buf_mut().marks.remove(&mark);
+    res
+}
+
+// Replaces the bytes start..end of a line with text, clamping end to the end of the line. None if
+// the line doesn't reach start
+fn change_span(line: &str, start: usize, end: usize, text: &str) -> Option<String> {
+    let end = end.min(line.len());
+    Some(format!("{}{}{}", line.get(..start)?, text, line.get(end..)?))
+}
+
+// Replaces the matches selected by flags and accepted by accept, which gets the start and end
+// of each match. Returns the new text and the number of replacements
+fn substitute_text<F>(re: &Regex, line: &str, rep: &mut Replacement, flags: &SubstituteFlags, mut accept: F) -> (String, usize)
+    where F: FnMut(usize, usize) -> bool {
+    let mut res = String::new();
+    let mut last = 0;
+    let mut count = 0;
+
+    for (i, caps) in re.captures_iter(line).enumerate() {
+        let n = i + 1;
+        if n < flags.nth || (n > flags.nth && !flags.global) {
+            continue;
+        }
+        let m = caps.get(0).unwrap();
+        if !accept(m.start(), m.end()) {
+            continue;
+        }
+        res.push_str(&line[last..m.start()]);
+        rep.replace_append(&caps, &mut res);
+        last = m.end();
+        count += 1;
+    }
+    res.push_str(&line[last..]);
+
+    (res, count)
+}
+
+// Shows a match highlighted in the line(s) it's on and asks whether to replace it.
+// Returns one of y(es), n(o), a(ll) or q(uit)
+fn confirm_replace(line_nr: usize, text: &str, start: usize, end: usize) -> char {
+    let line_start = text[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_end = text[end..].find('\n').map(|i| end + i).unwrap_or(text.len());
+
+    println!("{}{}{} {}{}{}{}{}",
+             fg(color::Cyan), format_number(line_nr), reset(),
+             &text[line_start..start],
+             bg(color::Red), &text[start..end], reset(),
+             &text[end..line_end]);
+    loop {
+        match read_line("y/n/a/q> ") {
+            Ok(ans) => {
+                match ans.trim().to_lowercase().chars().next() {
+                    Some(c) if "ynaq".contains(c) => return c,
+                    _ => { }
+                }
+            }
+            Err(_) => return 'q',
+        }
+    }
 }
 
 impl From<io::Error> for ActionErr {
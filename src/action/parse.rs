@@ -1,10 +1,13 @@
-use nom::IResult;
+use nom::{IResult, ErrorKind, Needed, multispace};
 
 use red_buffer::RedBuffer;
-use action::Action;
+use action::{Action, ChangeTarget, SubstituteFlags};
+use action::replace::Replacement;
 
 use range::parse::{parse_range, parse_usize};
 
+use regex;
+
 
 pub fn parse_action<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, Action> {
     alt_complete!(
@@ -15,6 +18,7 @@ pub fn parse_action<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, Actio
         apply!(delete, ctx) |
         flat_map!(tag!("i"), value!(Action::Insert)) |
         flat_map!(tag!("cl"), value!(Action::Clear)) |
+        apply!(change_text, ctx) |
         flat_map!(tag!("c"), value!(Action::Change)) |
         flat_map!(tag!("a"), value!(Action::Append)) |
         flat_map!(tag!("p"), value!(Action::Print)) |
@@ -24,13 +28,23 @@ pub fn parse_action<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, Actio
         flat_map!(tag!("<"), value!(Action::Unindent)) |
         apply!(insert, ctx) |
         apply!(append, ctx) |
+        apply!(record, ctx) |
+        apply!(replay, ctx) |
         apply!(regs, ctx) |
         apply!(set_mark, ctx) |
         apply!(buf_change, ctx) |
+        apply!(buf_do, ctx) |
         apply!(buf_new, ctx) |
+        apply!(quit_all, ctx) |
         apply!(buf_del, ctx) |
         apply!(copy_to, ctx) |
+        apply!(source, ctx) |
+        apply!(set, ctx) |
+        apply!(hist, ctx) |
         apply!(substitute, ctx) |
+        apply!(view, ctx) |
+        apply!(global, ctx) |
+        apply!(write_all, ctx) |
         apply!(write, ctx) |
         apply!(read, ctx)
         )
@@ -56,6 +70,34 @@ pub fn append<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
         )
 }
 
+pub fn change_text<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    alt_complete!(
+        inp,
+        do_parse!(
+            tag!("c") >>
+            start: parse_usize >>
+            tag!(",") >>
+            end: alt_complete!(
+                parse_usize |
+                value!(usize::max_value(), tag!("$"))
+                ) >>
+            tag!("/") >>
+            text: take_until_s!("/") >>
+            tag!("/") >>
+            (Action::ChangeText(ChangeTarget::Columns(start, end), text.to_string()))
+            ) |
+        do_parse!(
+            tag!("c/") >>
+            pattern: is_not_s!("/") >>
+            tag!("/") >>
+            text: take_until_s!("/") >>
+            tag!("/") >>
+            nth: opt!(complete!(parse_usize)) >>
+            (Action::ChangeText(ChangeTarget::Match(pattern.to_string(), nth.unwrap_or(1)), text.to_string()))
+            )
+        )
+}
+
 pub fn delete<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
     do_parse!(
         inp,
@@ -96,6 +138,33 @@ pub fn set_mark<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action>
         )
 }
 
+pub fn record<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    do_parse!(
+        inp,
+
+        tag!("rec") >>
+        register: opt!(complete!(is_not_s!(" "))) >>
+        (Action::Record(register.filter(|r| !r.is_empty()).map(|r| r.to_string())))
+        )
+}
+
+pub fn replay<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    alt_complete!(
+        inp,
+        do_parse!(
+            tag!("@@") >>
+            register: opt!(complete!(is_not_s!(" "))) >>
+            (Action::Replay(register.unwrap_or("").to_string(), None))
+            ) |
+        do_parse!(
+            tag!("@") >>
+            register: opt!(complete!(is_not_s!(" "))) >>
+            times: opt!(complete!(ws!(parse_usize))) >>
+            (Action::Replay(register.unwrap_or("").to_string(), Some(times.unwrap_or(1))))
+            )
+        )
+}
+
 pub fn regs<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
     alt_complete!(
         inp,
@@ -127,15 +196,49 @@ pub fn buf_del<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
         )
 }
 
+pub fn quit_all<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    alt_complete!(
+        inp,
+        do_parse!(
+            tag!("qa!") >>
+            (Action::QuitAll(true))
+            ) |
+        do_parse!(
+            tag!("qa") >>
+            (Action::QuitAll(false))
+            )
+        )
+}
+
 pub fn buf_change<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    alt_complete!(
+        inp,
+        do_parse!(
+            tag!("bc#") >>
+            (Action::BufAlternate)
+            ) |
+        // Like the other actions, bc<n> can be followed by another one
+        do_parse!(
+            tag!("bc") >>
+            buf: parse_usize >>
+            (Action::BufChange(buf))
+            ) |
+        do_parse!(
+            ws!(tag!("bc")) >>
+            name: is_not_s!("") >>
+            (Action::BufFind(name.to_string()))
+            )
+        )
+}
+
+pub fn buf_do<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
     do_parse!(
         inp,
 
-        tag!("bc") >>
-        buf: parse_usize >>
-        (Action::BufChange(buf)
+        ws!(tag!("bufdo")) >>
+        command: is_not_s!("") >>
+        (Action::BufDo(command.to_string()))
         )
-    )
 }
 
 pub fn buf_new<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
@@ -154,15 +257,133 @@ pub fn buf_new<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
         )
 }
 
+pub fn source<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    do_parse!(
+        inp,
+
+        ws!(tag!("source")) >>
+        name: is_not_s!("") >>
+        (Action::Source(name.to_string()))
+        )
+}
+
+pub fn set<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    alt_complete!(
+        inp,
+        do_parse!(
+            tag!("set") >>
+            eof!() >>
+            (Action::ShowOptions(None))
+            ) |
+        do_parse!(
+            ws!(tag!("set")) >>
+            name: is_not_s!("=? ") >>
+            tag!("?") >>
+            (Action::ShowOptions(Some(name.to_string())))
+            ) |
+        do_parse!(
+            ws!(tag!("set")) >>
+            name: is_not_s!("=? ") >>
+            value: opt!(complete!(preceded!(tag!("="), is_not_s!("")))) >>
+            (Action::Set(name.to_string(), value.map(|v| unquote(v.trim()).to_string())))
+            )
+        )
+}
+
+pub fn hist<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    do_parse!(
+        inp,
+
+        tag!("hist") >>
+        count: opt!(complete!(ws!(parse_usize))) >>
+        (Action::History(count))
+        )
+}
+
+// Text options can be quoted to keep spaces at the edges
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
 pub fn substitute<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
     do_parse!(
         inp,
 
-        tag!("s/") >>
-        pattern: is_not_s!("/") >>
-        tag!("/") >>
-        replace: is_not_s!("/") >>
-        (Action::Substitute(pattern.to_string(), replace.to_string()))
+        tag!("s") >>
+        delim: delimiter >>
+        pattern: apply!(delimited_part, delim, &regex::escape(&delim.to_string())) >>
+        char!(delim) >>
+        replace: apply!(delimited_part, delim, &delim.to_string()) >>
+        opt!(complete!(char!(delim))) >>
+        flags: opt!(complete!(is_a_s!("gicm0123456789"))) >>
+        (Action::Substitute(
+                pattern,
+                Replacement::new(&replace),
+                substitute_flags(flags.unwrap_or(""))
+                ))
+        )
+}
+
+fn substitute_flags(flags: &str) -> SubstituteFlags {
+    let digits: String = flags.chars().filter(|c| c.is_digit(10)).collect();
+    SubstituteFlags {
+        global: flags.contains('g'),
+        nth: digits.parse().unwrap_or(1).max(1),
+        case_insensitive: flags.contains('i'),
+        confirm: flags.contains('c'),
+        multi_line: flags.contains('m'),
+    }
+}
+
+// Any punctuation except for backslash can be used to separate the parts of an action
+pub fn delimiter(inp: &str) -> IResult<&str, char> {
+    match inp.chars().next() {
+        Some(c) if c.is_ascii_punctuation() && c != '\\' => IResult::Done(&inp[1..], c),
+        Some(_) => IResult::Error(error_position!(ErrorKind::Custom(0), inp)),
+        None => IResult::Incomplete(Needed::Size(1)),
+    }
+}
+
+// Reads everything up to the next unescaped delim. Escaped delimiters are replaced by
+// escaped_delim, other escapes are left untouched for the regex/replacement to handle.
+pub fn delimited_part<'a>(inp: &'a str, delim: char, escaped_delim: &str) -> IResult<&'a str, String> {
+    let mut res = String::new();
+    let mut chars = inp.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if c == delim {
+            return IResult::Done(&inp[i..], res);
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delim => res.push_str(escaped_delim),
+                Some((_, next)) => {
+                    res.push(c);
+                    res.push(next);
+                }
+                None => res.push(c),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    IResult::Done(&inp[inp.len()..], res)
+}
+
+pub fn global<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    do_parse!(
+        inp,
+
+        invert: alt!(value!(false, tag!("g")) | value!(true, tag!("v"))) >>
+        delim: delimiter >>
+        pattern: apply!(delimited_part, delim, &regex::escape(&delim.to_string())) >>
+        char!(delim) >>
+        command: opt!(complete!(is_not_s!(""))) >>
+        (Action::Global(invert, pattern, command.filter(|c| !c.trim().is_empty()).unwrap_or("p").to_string()))
         )
 }
 
@@ -176,6 +397,29 @@ pub fn copy_to<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, Action> {
         )
 }
 
+pub fn write_all<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    alt_complete!(
+        inp,
+        do_parse!(
+            tag!("wa") >>
+            eof!() >>
+            (Action::WriteAll)
+            ) |
+        do_parse!(
+            tag!("wq") >>
+            eof!() >>
+            (Action::WriteQuit(String::new()))
+            ) |
+        // Anything else after wq is a file name for w
+        do_parse!(
+            tag!("wq") >>
+            multispace >>
+            name: is_not_s!("") >>
+            (Action::WriteQuit(name.to_string()))
+            )
+        )
+}
+
 pub fn write<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
     do_parse!(
         inp,
@@ -186,6 +430,22 @@ pub fn write<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
         )
 }
 
+pub fn view<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
+    alt_complete!(
+        inp,
+        do_parse!(
+            ws!(tag!("view!")) >>
+            name: is_not_s!("") >>
+            (Action::View(true, name.to_string()))
+            ) |
+        do_parse!(
+            ws!(tag!("view")) >>
+            name: is_not_s!("") >>
+            (Action::View(false, name.to_string()))
+            )
+        )
+}
+
 pub fn read<'a>(inp: &'a str, _ctx: &RedBuffer) -> IResult<&'a str, Action> {
     alt!(
         inp,
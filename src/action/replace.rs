@@ -0,0 +1,99 @@
+use regex::{Captures, Replacer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),      // Text, possibly containing `$` references to capture groups
+    Counter,           // The number of the replacement
+    All(Option<Case>), // Convert everything after this, None ends the conversion
+    Next(Case),        // Convert the next character
+}
+
+// The replacement in s///, with support for case conversion and counting the replacements
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    parts: Vec<Part>,
+    count: usize,
+}
+
+impl Replacement {
+    // Parses the escapes in a replacement:
+    //     \\ and \n: A backslash and a newline
+    //     \U, \L: Make everything after this upper/lowercase, until \E
+    //     \u, \l: Make the next character upper/lowercase
+    //     \#: The number of the replacement, counting from 1
+    // Any other backslash is kept as is
+    pub fn new(rep: &str) -> Replacement {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = rep.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+            let part = match chars.next() {
+                Some('\\') => { text.push('\\'); continue; }
+                Some('n') => { text.push('\n'); continue; }
+                Some('U') => Part::All(Some(Case::Upper)),
+                Some('L') => Part::All(Some(Case::Lower)),
+                Some('E') => Part::All(None),
+                Some('u') => Part::Next(Case::Upper),
+                Some('l') => Part::Next(Case::Lower),
+                Some('#') => Part::Counter,
+                Some(next) => {
+                    text.push(c);
+                    text.push(next);
+                    continue;
+                }
+                None => { text.push(c); continue; }
+            };
+            if !text.is_empty() {
+                parts.push(Part::Text(text));
+                text = String::new();
+            }
+            parts.push(part);
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Replacement { parts: parts, count: 0 }
+    }
+}
+
+impl Replacer for Replacement {
+    fn replace_append(&mut self, caps: &Captures, dst: &mut String) {
+        self.count += 1;
+
+        let mut all = None;
+        let mut next = None;
+
+        for part in &self.parts {
+            let text = match *part {
+                Part::Text(ref text) => {
+                    let mut expanded = String::new();
+                    caps.expand(text, &mut expanded);
+                    expanded
+                }
+                Part::Counter => self.count.to_string(),
+                Part::All(case) => { all = case; continue; }
+                Part::Next(case) => { next = Some(case); continue; }
+            };
+
+            for c in text.chars() {
+                match next.take().or(all) {
+                    Some(Case::Upper) => dst.extend(c.to_uppercase()),
+                    Some(Case::Lower) => dst.extend(c.to_lowercase()),
+                    None => dst.push(c),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use nom::IResult;
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+
+use range::parse::parse_range;
+use action::parse::parse_action;
+use action::ActionErr;
+use red_master::RedMaster;
+use readline::{read_line, queue_commands, queue_parts, queued, clear_queue, is_interactive};
+
+lazy_static!{
+    // The exit code of the first error in a script
+    static ref STATUS: Mutex<Option<i32>> = Mutex::new(None);
+}
+
+// Runs a line of input, a range followed by any number of actions, on the current buffer.
+// The range becomes the new cursor. Stops at the first error
+pub fn run_command(line: &str, master: &mut RedMaster) -> Result<(), ActionErr> {
+    let mut line = match parse_range(line, master.curr_buf()) {
+        IResult::Done(rest, range) => {
+            // A range that was given but selects nothing is most likely a search that failed
+            if range.lines.is_empty() && rest.len() < line.len() {
+                return Err(ActionErr::NoMatch);
+            }
+            master.curr_buf_mut().cursor = range;
+            rest.to_string()
+        }
+        IResult::Error(e) => {
+            return Err(ActionErr::Parse(format!("Range error: {:?}", e)));
+        }
+        IResult::Incomplete(e) => {
+            return Err(ActionErr::Parse(format!("Range incomplete: {:?}", e)));
+        }
+    };
+
+    while line.trim().len() > 0 {
+        let lineclone = line.clone();
+        let action = parse_action(&lineclone.trim(), master.curr_buf());
+
+        match action {
+            IResult::Done(rest, action) => {
+                action.apply(master)?;
+                line = rest.to_string();
+            }
+            IResult::Error(e) => {
+                return Err(ActionErr::Parse(format!("Action parse error: {:?} from {:?}", e, line)));
+            }
+            IResult::Incomplete(e) => {
+                return Err(ActionErr::Parse(format!("Action incomplete: {:?}", e)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Runs lines as if they were typed at the prompt, so actions asking for input get the
+// following lines. A line can hold several commands separated by `;`, which are queued the
+// same way. Stops at the first error, skipping the rest of the lines
+pub fn run_lines(lines: Vec<String>, master: &mut RedMaster) -> Result<(), ActionErr> {
+    let remaining = queued();
+    queue_commands(lines);
+
+    // The commands split off a line are queued in front of the lines after it. Until the queue
+    // is back to parts_end, the lines read are those commands, which mustn't be split again
+    let mut parts_end = queued();
+
+    while queued() > remaining {
+        let is_part = queued() > parts_end;
+        if let Ok(line) = read_line("") {
+            let mut cmds = if is_part { vec![line] } else { split_commands(&line) };
+            let first = cmds.remove(0);
+            if !is_part {
+                parts_end = queued();
+                queue_parts(cmds);
+            }
+
+            if let Err(e) = run_command(&first, master) {
+                clear_queue(remaining);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Splits a line into the commands separated by `;`, without the spaces around them. `\;` is a `;`
+// that doesn't separate anything, and neither do the ones in regexes or between the delimiters of
+// `s`, `g`, `v` and `c`. File names and text given to actions are taken as they are, `;` and all
+pub fn split_commands(line: &str) -> Vec<String> {
+    let mut cmds = vec![String::new()];
+    let mut delim = None;     // The delimiter ending the current part, if in one
+    let mut delims_left = 0;  // The number of delimiters until the end of the delimited parts
+    let mut verbatim = false; // Don't look for delimiters in the rest of the command
+    let mut prev = ' ';
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let cmd = cmds.last_mut().unwrap();
+
+        if c == '\\' {
+            match chars.next() {
+                Some((_, ';')) if delim.is_none() => cmd.push(';'),
+                Some((_, next)) => {
+                    cmd.push(c);
+                    cmd.push(next);
+                }
+                None => cmd.push(c),
+            }
+        } else if let Some(d) = delim {
+            cmd.push(c);
+            if c == d {
+                delims_left -= 1;
+                if delims_left == 0 {
+                    delim = None;
+                }
+            }
+        } else if verbatim {
+            cmd.push(c);
+        } else if c == ';' {
+            cmds.push(String::new());
+        } else {
+            cmd.push(c);
+
+            // Actions can follow a range or another action without a space in between
+            let starts_word = !prev.is_alphabetic();
+            let next = chars.peek().map(|&(_, next)| next);
+            let next_delim = next.filter(|&n| n.is_ascii_punctuation() && n != '\\' && n != ';');
+
+            if c == '/' {
+                delim = Some('/');
+                delims_left = 1;
+            } else if starts_word && (c == 's' && next_delim.is_some() || c == 'c' && next == Some('/')) {
+                delim = next_delim;
+                delims_left = 3;
+            } else if starts_word && (c == 'g' || c == 'v') && next_delim.is_some() {
+                delim = next_delim;
+                delims_left = 2;
+            } else if starts_word && prev != '\'' && prev != '@' && takes_text(&line[i..]) {
+                verbatim = true;
+            }
+        }
+        prev = c;
+    }
+    cmds.into_iter().map(|cmd| cmd.trim().to_string()).collect()
+}
+
+// Whether rest starts with an action whose argument is taken as it is. The text of `I` and `A` can
+// be anything, but a file name can't start with `;`, so `w;q` is still two commands
+fn takes_text(rest: &str) -> bool {
+    if rest.starts_with('I') || rest.starts_with('A') {
+        return true;
+    }
+    match ["wa", "wq", "e", "w", "bn", "view", "source", "set"].iter().find(|w| rest.starts_with(*w)) {
+        Some(w) => {
+            let arg = rest[w.len()..].trim_start();
+            !arg.is_empty() && !arg.starts_with(';')
+        }
+        None => false,
+    }
+}
+
+// Runs every line in a file as a command. Empty lines and lines starting with # are skipped
+pub fn source_file<P: AsRef<Path>>(path: P, master: &mut RedMaster) -> Result<(), ActionErr> {
+    run_lines(script_lines(path)?, master)
+}
+
+// The commands in a file, without the empty lines and comments
+pub fn script_lines<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+
+    Ok(content.lines()
+        .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+        .map(|l| l.to_string())
+        .collect())
+}
+
+// Replaces $0 in a line of a script with its path, and $1, $2... with the arguments given after
+// it, and $$ with a $. The replacements of `s` and `c` are left alone, as $1 there is a capture
+// group. Fails on an argument that wasn't given
+pub fn expand_args(line: &str, args: &[String]) -> Result<String, String> {
+    let mut res = String::new();
+    let mut delim = None;    // The delimiter of the `s` or `c` being read, if in one
+    let mut delims_left = 0; // The number of delimiters until the end of the replacement
+    let mut prev = ' ';
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if let Some(d) = delim {
+            if c == '\\' {
+                res.push(c);
+                if let Some(next) = chars.next() {
+                    res.push(next);
+                }
+                continue;
+            }
+            if c == d {
+                delims_left -= 1;
+                if delims_left == 0 {
+                    delim = None;
+                }
+            }
+            // Only the last delimiter is left after the replacement
+            if c == d || delims_left == 1 {
+                res.push(c);
+                prev = c;
+                continue;
+            }
+        }
+
+        if c == '$' {
+            let mut n = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                n.push(d);
+                chars.next();
+            }
+            if !n.is_empty() {
+                match n.parse().ok().and_then(|n: usize| args.get(n)) {
+                    Some(arg) => res.push_str(arg),
+                    None => return Err(format!("No argument ${} given to the script", n)),
+                }
+            } else if chars.peek() == Some(&'$') {
+                chars.next();
+                res.push('$');
+            } else {
+                res.push('$');
+            }
+        } else {
+            // Like in split_commands, `s` and `c` can follow a range or an action directly
+            let next = chars.peek().cloned();
+            let next_delim = next.filter(|&n| n.is_ascii_punctuation() && n != '\\' && n != ';');
+            if delim.is_none() && !prev.is_alphabetic() && (c == 's' && next_delim.is_some() || c == 'c' && next == Some('/')) {
+                delim = next_delim;
+                delims_left = 3;
+            }
+            res.push(c);
+        }
+        prev = c;
+    }
+    Ok(res)
+}
+
+// Remembers that something failed, so red can exit with a matching status
+pub fn set_failed(err: &ActionErr) {
+    let mut status = STATUS.lock().unwrap();
+    if status.is_none() {
+        *status = Some(err.exit_code());
+    }
+}
+
+// The status to exit with: that of the first error in a script, 0 if it succeeded or if the
+// commands were typed by the user
+pub fn exit_status() -> i32 {
+    if is_interactive() {
+        0
+    } else {
+        STATUS.lock().unwrap().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_commands;
+
+    fn split(line: &str) -> Vec<String> {
+        split_commands(line)
+    }
+
+    #[test]
+    fn splits_on_semicolons() {
+        assert_eq!(split("1p;/fn/d"), vec!["1p", "/fn/d"]);
+        assert_eq!(split("0P; 1P"), vec!["0P", "1P"]);
+        assert_eq!(split("0i;first line;."), vec!["0i", "first line", "."]);
+    }
+
+    #[test]
+    fn escaped_semicolons() {
+        assert_eq!(split(r"bufdo %s/a/b/g\;w"), vec!["bufdo %s/a/b/g;w"]);
+        assert_eq!(split(r"s/a\;/b/;p"), vec![r"s/a\;/b/", "p"]);
+    }
+
+    #[test]
+    fn delimiters() {
+        assert_eq!(split("/a;b/p;2p"), vec!["/a;b/p", "2p"]);
+        assert_eq!(split("%s/;/,/g;p"), vec!["%s/;/,/g", "p"]);
+        assert_eq!(split("%s|a|;|;w"), vec!["%s|a|;|", "w"]);
+        assert_eq!(split("g/x;y/d;p"), vec!["g/x;y/d", "p"]);
+        assert_eq!(split("%c/x;/y/;p"), vec!["%c/x;/y/", "p"]);
+    }
+
+    #[test]
+    fn text_is_verbatim() {
+        assert_eq!(split("%A;"), vec!["%A;"]);
+        assert_eq!(split("I // a; b/c"), vec!["I // a; b/c"]);
+        assert_eq!(split("e a;b.txt"), vec!["e a;b.txt"]);
+        assert_eq!(split("1,2A ;x"), vec!["1,2A ;x"]);
+    }
+
+    #[test]
+    fn bare_file_actions() {
+        assert_eq!(split("w;q"), vec!["w", "q"]);
+        assert_eq!(split("wa;qa"), vec!["wa", "qa"]);
+        assert_eq!(split("wq ; p"), vec!["wq", "p"]);
+    }
+
+    #[test]
+    fn marks_and_registers() {
+        assert_eq!(split("'A;p"), vec!["'A", "p"]);
+        assert_eq!(split("@A;p"), vec!["@A", "p"]);
+    }
+}
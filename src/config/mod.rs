@@ -1,9 +1,197 @@
 use std::sync::Mutex;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
 
 pub struct Config {
-    pub silent: bool
+    pub silent: bool,
+    pub indent_width: usize, // Number of spaces per indentation level
+    pub tabs: bool,          // Indent using tabs instead of spaces
+    pub tab_width: usize,    // Number of columns a tab counts as
+    pub detect_indent: bool, // Follow the indentation of opened files instead of the options above
+    pub editorconfig: bool,  // Apply the .editorconfig files for opened files
+    pub autoindent: bool,    // Indent inserted lines like the line above them
+    pub smartindent: bool,   // Indent inserted lines one level more after a line opening a block
+    pub colors: bool,
+    pub base: u32,           // The base line numbers are shown in
+    pub prompt: String,
+    pub backup: bool,        // Keep a copy of a file as file~ when overwriting it
+    pub history: usize,      // Number of commands to remember, also across sessions
+    pub preview: bool,       // Show what the range being typed selects
+    pub exrc: bool,          // Run the .redrc in the current directory too
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionValue {
+    Bool(bool),
+    Number(usize),
+    Text(String),
+}
+
+pub static OPTIONS: &[&str] = &[
+    "silent", "indentwidth", "tabs", "tabwidth", "detectindent", "editorconfig", "autoindent", "smartindent",
+    "colors", "base", "prompt", "backup", "history", "preview", "exrc",
+];
+
 lazy_static! {
-    pub static ref CONF: Mutex<Config> = Mutex::new(Config { silent: false });
+    pub static ref CONF: Mutex<Config> = Mutex::new(Config {
+        silent: false,
+        indent_width: 4,
+        tabs: false,
+        tab_width: 8,
+        detect_indent: true,
+        editorconfig: true,
+        autoindent: false,
+        smartindent: false,
+        colors: true,
+        base: 10,
+        prompt: String::new(),
+        backup: false,
+        history: 1000,
+        preview: true,
+        exrc: false,
+    });
+}
+
+impl Config {
+    pub fn get(&self, name: &str) -> Option<OptionValue> {
+        Some(match name {
+            "silent" => OptionValue::Bool(self.silent),
+            "indentwidth" => OptionValue::Number(self.indent_width),
+            "tabs" => OptionValue::Bool(self.tabs),
+            "tabwidth" => OptionValue::Number(self.tab_width),
+            "detectindent" => OptionValue::Bool(self.detect_indent),
+            "editorconfig" => OptionValue::Bool(self.editorconfig),
+            "autoindent" => OptionValue::Bool(self.autoindent),
+            "smartindent" => OptionValue::Bool(self.smartindent),
+            "colors" => OptionValue::Bool(self.colors),
+            "base" => OptionValue::Number(self.base as usize),
+            "prompt" => OptionValue::Text(self.prompt.clone()),
+            "backup" => OptionValue::Bool(self.backup),
+            "history" => OptionValue::Number(self.history),
+            "preview" => OptionValue::Bool(self.preview),
+            "exrc" => OptionValue::Bool(self.exrc),
+            _ => return None,
+        })
+    }
+
+    // Sets an option from its textual value. Flags without a value are turned on,
+    // and `set noflag` turns them off
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let current = match self.get(name) {
+            Some(current) => current,
+            None if name.starts_with("no") && value.is_none() => {
+                return match self.get(&name[2..]) {
+                    Some(OptionValue::Bool(_)) => self.set(&name[2..], Some("false")),
+                    _ => Err(format!("No such option: {}", name)),
+                };
+            }
+            None => return Err(format!("No such option: {}", name)),
+        };
+
+        let new = match (current, value) {
+            (OptionValue::Bool(_), value) => OptionValue::Bool(parse_bool(value)?),
+            (OptionValue::Number(_), Some(value)) => {
+                OptionValue::Number(value.parse().map_err(|_| format!("Expected a number, got {}", value))?)
+            }
+            (OptionValue::Number(_), None) => return Err(format!("{} needs a value", name)),
+            (OptionValue::Text(_), value) => OptionValue::Text(value.unwrap_or("").to_string()),
+        };
+
+        match (name, new) {
+            ("silent", OptionValue::Bool(x)) => self.silent = x,
+            ("indentwidth", OptionValue::Number(x)) => self.indent_width = x,
+            ("tabs", OptionValue::Bool(x)) => self.tabs = x,
+            ("tabwidth", OptionValue::Number(x)) => {
+                if x == 0 {
+                    return Err("The tab width can't be 0".into());
+                }
+                self.tab_width = x;
+            }
+            ("detectindent", OptionValue::Bool(x)) => self.detect_indent = x,
+            ("editorconfig", OptionValue::Bool(x)) => self.editorconfig = x,
+            ("autoindent", OptionValue::Bool(x)) => self.autoindent = x,
+            ("smartindent", OptionValue::Bool(x)) => self.smartindent = x,
+            ("colors", OptionValue::Bool(x)) => self.colors = x,
+            ("base", OptionValue::Number(x)) => {
+                if ![2, 8, 10, 16].contains(&x) {
+                    return Err("The base has to be 2, 8, 10 or 16".into());
+                }
+                self.base = x as u32;
+            }
+            ("prompt", OptionValue::Text(x)) => self.prompt = x,
+            ("backup", OptionValue::Bool(x)) => self.backup = x,
+            ("history", OptionValue::Number(x)) => self.history = x,
+            ("preview", OptionValue::Bool(x)) => self.preview = x,
+            ("exrc", OptionValue::Bool(x)) => self.exrc = x,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // The string to indent a line one level with
+    pub fn indent_unit(&self) -> String {
+        if self.tabs {
+            "\t".into()
+        } else {
+            " ".repeat(self.indent_width)
+        }
+    }
+
+    pub fn format_number(&self, n: usize) -> String {
+        match self.base {
+            2 => format!("{:b}", n),
+            8 => format!("{:o}", n),
+            16 => format!("{:x}", n),
+            _ => n.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OptionValue::Bool(x) => write!(f, "{}", x),
+            OptionValue::Number(x) => write!(f, "{}", x),
+            OptionValue::Text(ref x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+// The user's configuration file, run at startup
+pub fn user_rc() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.clone().map(|h| h.join(".config")));
+
+    config_dir.map(|c| c.join("red").join("redrc"))
+        .filter(|p| p.is_file())
+        .or_else(|| home.map(|h| h.join(".redrc")))
+        .filter(|p| p.is_file())
+}
+
+// The configuration file in the current directory, unless it's the user's own. It's only run
+// when the user's file turns on exrc
+pub fn local_rc() -> Option<PathBuf> {
+    let local_rc = PathBuf::from(".redrc");
+    let is_user_rc = user_rc().map(|f| f.canonicalize().ok() == local_rc.canonicalize().ok()).unwrap_or(false);
+    Some(local_rc).filter(|f| f.is_file() && !is_user_rc)
+}
+
+// Where the command history is kept between sessions
+pub fn history_file() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("state")))
+        .map(|state| state.join("red").join("history"))
+}
+
+// A flag without a value is turned on
+pub fn parse_bool(value: Option<&str>) -> Result<bool, String> {
+    match value {
+        None | Some("true") | Some("on") | Some("yes") => Ok(true),
+        Some("false") | Some("off") | Some("no") => Ok(false),
+        Some(x) => Err(format!("Expected true or false, got {}", x)),
+    }
 }
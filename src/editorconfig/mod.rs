@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use red_buffer::RedBuffer;
+use red_buffer::Indent;
+use red_buffer::format::{Charset, LineEnding};
+use config;
+
+// Finds the properties set for a file by the .editorconfig files in its directory and the ones
+// above it, up to the one with root = true. Closer files take precedence
+pub fn properties(path: &str) -> HashMap<String, String> {
+    let mut props = HashMap::new();
+
+    let path = match absolute(Path::new(path)) {
+        Some(path) => path,
+        None => return props,
+    };
+
+    let mut configs = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let mut content = String::new();
+        if let Ok(mut f) = File::open(dir.join(".editorconfig")) {
+            if f.read_to_string(&mut content).is_ok() {
+                let root = is_root(&content);
+                configs.push((dir.to_path_buf(), content));
+                if root {
+                    break;
+                }
+            }
+        }
+    }
+
+    for (dir, content) in configs.into_iter().rev() {
+        let relative = match path.strip_prefix(&dir) {
+            Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+            Err(_) => continue,
+        };
+
+        let mut matching = false;
+        for line in content.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                matching = glob_matches(&line[1..line.len() - 1], &relative);
+            } else if matching {
+                if let Some(eq) = line.find('=') {
+                    let key = line[..eq].trim().to_lowercase();
+                    let value = line[eq + 1..].trim().to_lowercase();
+                    props.insert(key, value);
+                }
+            }
+        }
+    }
+
+    props
+}
+
+pub fn charset(props: &HashMap<String, String>) -> Option<Charset> {
+    match props.get("charset").map(|c| &**c) {
+        Some("utf-8") => Some(Charset::Utf8),
+        Some("utf-8-bom") => Some(Charset::Utf8Bom),
+        Some("latin1") => Some(Charset::Latin1),
+        Some("utf-16be") => Some(Charset::Utf16Be),
+        Some("utf-16le") => Some(Charset::Utf16Le),
+        _ => None,
+    }
+}
+
+// Applies everything except for the charset, which has to be known before reading the file
+pub fn apply(props: &HashMap<String, String>, buf: &mut RedBuffer) {
+    let get = |key: &str| props.get(key).map(|v| &**v);
+
+    let size = match get("indent_size") {
+        Some("tab") => Some(config::CONF.lock().unwrap().tab_width),
+        Some(size) => size.parse().ok(),
+        None => None,
+    };
+    match (get("indent_style"), size) {
+        (Some("tab"), _) => buf.indent = Some(Indent::Tabs),
+        (Some("space"), Some(size)) => buf.indent = Some(Indent::Spaces(size)),
+        (Some("space"), None) => {
+            let width = config::CONF.lock().unwrap().indent_width;
+            buf.indent = Some(Indent::Spaces(width));
+        }
+        (None, Some(size)) => {
+            if buf.indent != Some(Indent::Tabs) {
+                buf.indent = Some(Indent::Spaces(size));
+            }
+        }
+        _ => { }
+    }
+
+    match get("end_of_line") {
+        Some("lf") => buf.format.line_ending = LineEnding::Lf,
+        Some("crlf") => buf.format.line_ending = LineEnding::CrLf,
+        Some("cr") => buf.format.line_ending = LineEnding::Cr,
+        _ => { }
+    }
+    match get("insert_final_newline") {
+        Some("true") => buf.format.final_newline = true,
+        Some("false") => buf.format.final_newline = false,
+        _ => { }
+    }
+    match get("trim_trailing_whitespace") {
+        Some("true") => buf.format.trim_trailing_whitespace = true,
+        Some("false") => buf.format.trim_trailing_whitespace = false,
+        _ => { }
+    }
+}
+
+fn absolute(path: &Path) -> Option<PathBuf> {
+    let path = env::current_dir().ok()?.join(path);
+    let name = path.file_name()?.to_owned();
+    let dir = path.parent()?.canonicalize().ok()?;
+    Some(dir.join(name))
+}
+
+fn is_root(content: &str) -> bool {
+    for line in content.lines().map(|l| l.trim()) {
+        if line.starts_with('[') {
+            return false;
+        }
+        if let Some(eq) = line.find('=') {
+            if line[..eq].trim().eq_ignore_ascii_case("root") && line[eq + 1..].trim().eq_ignore_ascii_case("true") {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Globs without a / match the file name in any directory, the others are relative to the
+// directory of the .editorconfig
+fn glob_matches(glob: &str, path: &str) -> bool {
+    let pattern =
+        if glob.contains('/') {
+            format!("^{}$", glob_to_regex(glob.trim_start_matches('/')))
+        } else {
+            format!("^(.*/)?{}$", glob_to_regex(glob))
+        };
+    Regex::new(&pattern).map(|re| re.is_match(path)).unwrap_or(false)
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut res = String::new();
+    let mut chars = glob.chars().peekable();
+    let mut braces = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                res.push_str(".*");
+            }
+            '*' => res.push_str("[^/]*"),
+            '?' => res.push_str("[^/]"),
+            '[' => {
+                res.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    res.push('^');
+                }
+                while let Some(c) = chars.next() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        res.push('\\');
+                    }
+                    res.push(c);
+                }
+                res.push(']');
+            }
+            '{' => {
+                let rest: String = chars.clone().collect();
+                if let Some(range) = rest.find('}').and_then(|end| number_range(&rest[..end])) {
+                    res.push_str(&range);
+                    for _ in 0..rest.find('}').unwrap() + 1 {
+                        chars.next();
+                    }
+                } else {
+                    braces += 1;
+                    res.push_str("(?:");
+                }
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                res.push(')');
+            }
+            ',' if braces > 0 => res.push('|'),
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    res.push_str(&::regex::escape(&next.to_string()));
+                }
+            }
+            c => res.push_str(&::regex::escape(&c.to_string())),
+        }
+    }
+    res
+}
+
+// {n1..n2} matches any integer between n1 and n2
+fn number_range(inner: &str) -> Option<String> {
+    let mut parts = inner.splitn(2, "..");
+    let start: i64 = parts.next()?.parse().ok()?;
+    let end: i64 = parts.next()?.parse().ok()?;
+    if end < start || end - start > 1000 {
+        return None;
+    }
+    let options: Vec<String> = (start..end + 1).map(|n| n.to_string()).collect();
+    Some(format!("(?:{})", options.join("|")))
+}
@@ -16,132 +16,326 @@ mod action;
 mod red_master;
 mod readline;
 mod config;
+mod command;
+mod editorconfig;
 
-use readline::{read_line, add_command};
+use readline::{read_line, set_commands, update_state, is_interactive};
+use readline::{load_history, save_history, add_history, expand_history};
 
 use std::env::args;
+use std::fs;
+use std::io;
 use std::io::{stdin, Read};
+use std::path::Path;
+use std::process::exit;
 
-use nom::IResult;
-
-use range::parse::parse_range;
-use action::parse::parse_action;
 use action::Action;
 use action::ActionErr;
 use red_buffer::RedBuffer;
 use red_master::RedMaster;
 use range::Range;
+use command::{run_command, run_lines, source_file, script_lines, expand_args, set_failed, exit_status};
 
 fn main() {
     let mut file = RedMaster::empty();
 
+    if !args().any(|arg| arg == "--norc") {
+        if let Some(rc) = config::user_rc() {
+            source_rc(&rc, &mut file);
+        }
+        // Anyone can put a .redrc in a directory, so it's only run when the user's own file turns
+        // on exrc, and never for scripts
+        let scripted = args().skip(1).any(|arg| arg == "-d" || arg == "-f" || arg == "-p" || arg.starts_with("-i"));
+        if config::CONF.lock().unwrap().exrc && !scripted {
+            if let Some(rc) = config::local_rc() {
+                source_rc(&rc, &mut file);
+            }
+        }
+    }
+
     let mut args = args().skip(1); // Remove file path
+    let mut stop_on_error = false;
+    let mut stream = false;
+    let mut in_place: Option<String> = None; // The suffix of the backups, if editing in place
+    let mut from_stdin = false;
+    let mut paths = Vec::new();
+    let mut script = None;
+    let mut start_range = None; // Where to put the cursor in the first file
+    let mut startup_commands = Vec::new();
+    let mut read_only = false;
 
     while let Some(arg) = args.next() {
         if arg == "-h" {
             println!("{}", include_str!("help.txt"));
             return;
+        } else if arg == "--version" {
+            println!("red {}", env!("CARGO_PKG_VERSION"));
+            return;
+        } else if arg == "--norc" {
+            // Already handled before sourcing the configuration files
         } else if arg == "-s" {
             config::CONF.lock().unwrap().silent = true;
+        } else if arg == "-e" {
+            stop_on_error = true;
+        } else if arg == "-p" {
+            stream = true;
+        } else if arg == "-R" {
+            read_only = true;
+        } else if arg == "-c" {
+            match args.next() {
+                Some(cmd) => startup_commands.push(cmd),
+                None => {
+                    eprintln!("-c needs a command to run");
+                    exit(2);
+                }
+            }
+        } else if arg.starts_with('+') && arg.len() > 1 {
+            start_range = Some(arg[1..].to_string());
+        } else if arg.starts_with("-i") {
+            in_place = Some(arg[2..].to_string());
         } else if arg == "-d" {
-            while let Some(command_to_exec) = args.next() {
-                add_command(command_to_exec);
+            script = Some(args.by_ref().collect::<Vec<_>>());
+        } else if arg == "-f" {
+            // The arguments after the script are for the script, with $0 being the script itself
+            let script_args: Vec<String> = args.by_ref().collect();
+            let lines = match script_args.first() {
+                Some(path) => script_lines(path),
+                None => {
+                    eprintln!("-f needs a script to run");
+                    exit(2);
+                }
+            };
+            let lines = match lines {
+                Ok(lines) => lines,
+                Err(e) => {
+                    eprintln!("Couldn't read script! ({:?})", e);
+                    exit(3);
+                }
+            };
+            match lines.iter().map(|l| expand_args(l, &script_args)).collect() {
+                Ok(lines) => script = Some(lines),
+                Err(msg) => {
+                    eprintln!("{}", msg);
+                    exit(2);
+                }
             }
         } else if arg == "--" {
-            // Read buffer from STDIN
-            let mut data = String::new();
-            if let Err(e) = stdin().read_to_string(&mut data) {
-                eprintln!("Couldn't read from STDIN: {:?}", e);
+            from_stdin = true;
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    // Anything but the final buffer on STDOUT would get mixed up with it
+    if stream || in_place.is_some() {
+        config::CONF.lock().unwrap().silent = true;
+    }
+
+    if let Some(suffix) = in_place {
+        // Files given after -d would be taken as commands
+        if paths.is_empty() {
+            eprintln!("-i needs files to edit, given before -d or -f");
+            exit(2);
+        }
+        let script = script.unwrap_or(vec![]);
+        for path in paths {
+            match edit_in_place(&mut file, &path, &suffix, script.clone(), stop_on_error) {
+                Ok(true) => {
+                    if stream {
+                        print_buffer(file.curr_buf());
+                    }
+                }
+                Ok(false) => break,
+                Err(err) => {
+                    eprintln!("Couldn't edit {}! ({:?})", path, err);
+                    set_failed(&err);
+                    if stop_on_error {
+                        break;
+                    }
+                }
             }
+        }
+        exit(exit_status());
+    }
 
-            let mut buf = RedBuffer::empty();
-            buf.lines = data.lines().map(|x| x.to_string()).collect();
-            buf.cursor = Range::empty();
-            if !config::CONF.lock().unwrap().silent {
-                println!("Editing [STDIN] [{}]", buf.lines.len());
+    if from_stdin || (stream && paths.is_empty()) {
+        read_stdin(&mut file);
+    }
+    // Every file gets a buffer of its own, starting with the first one
+    for (i, path) in paths.into_iter().enumerate() {
+        let open =
+            if i == 0 && !from_stdin {
+                Action::Edit(true, path)
+            } else {
+                Action::BufNew(Some(path))
+            };
+        if let Err(err) = open.apply(&mut file) {
+            if let ActionErr::IO(ref err) = err {
+                eprintln!("Couldn't read file! ({:?})", err);
             }
+            set_failed(&err);
+        }
+    }
+    let _ = file.switch_buffer(0);
 
-            file.buffers = vec![buf];
-        } else if let Err(ActionErr::IO(err)) = Action::Edit(true, arg).apply(&mut file) {
-            eprintln!("Couldn't read file! ({:?})", err);
+    if read_only {
+        for buf in file.buffers.iter_mut() {
+            buf.read_only = true;
+        }
+    }
+    if let Some(range) = start_range {
+        if let Err(err) = run_command(&range, &mut file) {
+            report_error(&err);
+            set_failed(&err);
+        }
+    }
+    for cmd in startup_commands {
+        if let Err(err) = run_lines(vec![cmd], &mut file) {
+            report_error(&err);
+            set_failed(&err);
         }
     }
 
-    let mut last_line = "".to_string();
+    // Stream mode only reads commands from -d
+    if script.is_some() || stream {
+        set_commands(script.unwrap_or(vec![]));
+    }
+
+    if is_interactive() {
+        load_history();
+    }
 
+    run(&mut file, stop_on_error);
+    if stream {
+        for buf in &file.buffers {
+            print_buffer(buf);
+        }
+    }
+
+    exit(exit_status());
+}
+
+// Reads and runs commands until there are no more. Returns false if stopped by an error
+fn run(file: &mut RedMaster, stop_on_error: bool) -> bool {
     let mut quitting = false;
 
     loop {
-        let line = read_line("");
+        let prompt = config::CONF.lock().unwrap().prompt.clone();
+        // Only the completion and hints at the prompt need it
+        if is_interactive() {
+            update_state(file);
+        }
+        let line = read_line(&prompt);
 
         if let Err(_) = line {
             if config::CONF.lock().unwrap().silent {
-                break;
+                return true;
             }
-            let buf = file.curr_buf();
-            if !buf.saved {
+            let unsaved = file.unsaved_buffers();
+            if !unsaved.is_empty() {
                 if quitting {
                     eprintln!("STDIN borked. Quitting");
-                    break;
+                    return true;
                 }
-                eprintln!("File not saved! Type q! to force quit.");
+                eprintln!("Not saved: {}! Type qa! to force quit.", unsaved.join(", "));
                 quitting = true;
                 continue;
             }
-            break;
+            return true;
         }
 
         quitting = false;
 
-        let mut line = line.unwrap();
-
-        if line.trim() == "!" {
-            line = last_line.clone();
-        }
-        last_line = line.clone();
-
-        let lineclone = line.clone();
-        let range = parse_range(
-                &lineclone,
-                &file.curr_buf()
-                );
-
-        match range {
-            IResult::Done(rest, range) => {
-                file.curr_buf_mut().cursor = range;
-                line = rest.to_string();
-            }
-            IResult::Error(e) => {
-                eprintln!("Range error: {:?}", e);
-                continue;
+        // Only the user recalls commands, in scripts !N is an inverted range
+        let line = line.unwrap();
+        let line = if is_interactive() {
+            match expand_history(&line) {
+                Ok(line) => line,
+                Err(msg) => {
+                    eprintln!("{}", msg);
+                    set_failed(&ActionErr::Other);
+                    continue;
+                }
             }
-            IResult::Incomplete(e) => {
-                eprintln!("Range incomplete: {:?}", e);
-                continue;
+        } else {
+            line
+        };
+        add_history(&line);
+        if is_interactive() {
+            save_history();
+        }
+
+        if let Err(err) = run_lines(vec![line], file) {
+            report_error(&err);
+            set_failed(&err);
+            if stop_on_error && !is_interactive() {
+                return false;
             }
         }
+    }
+}
 
-        while line.trim().len() > 0 {
-            let lineclone = line.clone();
-            let action = parse_action(&lineclone.trim(), &file.curr_buf());
+// Runs the script on the file and writes it back if it was changed, first copying the
+// original to the path with suffix appended, unless the suffix is empty. Returns false if
+// the script was stopped by an error
+fn edit_in_place(file: &mut RedMaster, path: &str, suffix: &str, script: Vec<String>, stop_on_error: bool) -> Result<bool, ActionErr> {
+    if !Path::new(path).is_file() {
+        return Err(ActionErr::IO(io::Error::new(io::ErrorKind::NotFound, "No such file")));
+    }
+    Action::Edit(true, path.to_string()).apply(file)?;
 
-            match action {
-                IResult::Done(rest, action) => {
-                    if let Err(x) = action.apply(&mut file) {
-                        eprintln!("Application error: {:?}", x);
-                    }
-                    line = rest.to_string();
-                }
-                IResult::Error(e) => {
-                    eprintln!("Action parse error: {:?} from {:?}", e, line);
-                    break;
-                }
-                IResult::Incomplete(e) => {
-                    eprintln!("Action incomplete: {:?}", e);
-                    break;
-                }
-            }
+    set_commands(script);
+    if !run(file, stop_on_error) {
+        return Ok(false);
+    }
+
+    if !file.curr_buf().saved {
+        if !suffix.is_empty() {
+            fs::copy(path, format!("{}{}", path, suffix))?;
         }
+        Action::Write(path.to_string()).apply(file)?;
+    }
+    Ok(true)
+}
+
+fn report_error(err: &ActionErr) {
+    match *err {
+        ActionErr::Parse(ref msg) => eprintln!("{}", msg),
+        ActionErr::NoMatch => eprintln!("No match"),
+        ActionErr::ReadOnly => eprintln!("The buffer is read-only! Use `set noreadonly` to allow changes"),
+        ref x => eprintln!("Application error: {:?}", x),
+    }
+}
+
+fn source_rc(rc: &Path, file: &mut RedMaster) {
+    if let Err(e) = source_file(rc, file) {
+        eprintln!("Error in {}: {:?}", rc.display(), e);
+    }
+}
+
+fn read_stdin(file: &mut RedMaster) {
+    let mut data = String::new();
+    if let Err(e) = stdin().read_to_string(&mut data) {
+        eprintln!("Couldn't read from STDIN: {:?}", e);
+        set_failed(&ActionErr::IO(e));
+    }
+
+    let mut buf = RedBuffer::empty();
+    buf.lines = data.lines().map(|x| x.to_string()).collect();
+    buf.cursor = Range::empty();
+    if config::CONF.lock().unwrap().detect_indent {
+        buf.detect_indent();
+    }
+    if !config::CONF.lock().unwrap().silent {
+        println!("Editing [STDIN] [{}]", buf.lines.len());
     }
 
-}
\ No newline at end of file
+    buf.id = file.curr_buf().id;
+    *file.curr_buf_mut() = buf;
+}
+
+fn print_buffer(buf: &RedBuffer) {
+    for line in &buf.lines {
+        println!("{}", line);
+    }
+}
@@ -2,6 +2,7 @@ pub mod parse;
 
 use std::collections::HashSet;
 use red_buffer::RedBuffer;
+use config;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Range {
@@ -36,7 +37,16 @@ impl Range {
     pub fn removed_line(self, at: usize) -> Range {
         let res_lines: HashSet<usize> =
                 self.lines.iter()
-                    .map(|&l| if l >= at { l - 1 } else { l })
+                    .map(|&l| if l >= at { l.saturating_sub(1) } else { l })
+                    .collect();
+        Range { lines: res_lines }
+    }
+    // Like removed_line, but the removed line is no longer part of the range
+    pub fn deleted_line(self, at: usize) -> Range {
+        let res_lines: HashSet<usize> =
+                self.lines.iter()
+                    .filter(|&&l| l != at)
+                    .map(|&l| if l > at { l - 1 } else { l })
                     .collect();
         Range { lines: res_lines }
     }
@@ -45,11 +55,22 @@ impl Range {
     }
 }
 
+// The number of lines a piece of text spans, not counting a trailing newline as starting a new line
+pub fn spanned_lines(text: &str) -> usize {
+    let newlines = text.matches('\n').count();
+    if text.ends_with('\n') {
+        newlines
+    } else {
+        newlines + 1
+    }
+}
+
 fn line_to_block(line: usize, ctx: &RedBuffer) -> Vec<usize> {
-    if let Some(depth) = ctx.lines.get(line).and_then(|c| get_depth(c)) {
+    let tab_width = config::CONF.lock().unwrap().tab_width;
+    if let Some(depth) = ctx.lines.get(line).and_then(|c| get_depth(c, tab_width)) {
         let mut last = line + 1;
         while last < ctx.lines.len() {
-            match get_depth(ctx.lines.get(last).unwrap()) {
+            match get_depth(ctx.lines.get(last).unwrap(), tab_width) {
                 Some(d) if d <= depth => { break }
                 _ => { }
             }
@@ -61,10 +82,18 @@ fn line_to_block(line: usize, ctx: &RedBuffer) -> Vec<usize> {
     }
 }
 
-fn get_depth(line: &str) -> Option<usize> {
+// The column the content of a line starts at, with tabs going to the next multiple of tab_width
+fn get_depth(line: &str, tab_width: usize) -> Option<usize> {
     if line.trim().is_empty() {
-        None
-    } else {
-        line.find(|x| x != ' ')
+        return None;
     }
-}
\ No newline at end of file
+    let mut depth = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => depth += 1,
+            '\t' => depth += tab_width - depth % tab_width,
+            _ => break,
+        }
+    }
+    Some(depth)
+}
@@ -1,9 +1,9 @@
 use std::collections::HashSet;
 use nom::{IResult, digit};
 
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 use red_buffer::RedBuffer;
-use range::Range;
+use range::{Range, spanned_lines};
 
 pub fn parse_range<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, Range> {
     alt_complete!(
@@ -160,7 +160,7 @@ fn line<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, usize> {
         parse_usize |
         do_parse!(
             tag!("$") >>
-            ( ctx.lines.len() - 1 )
+            ( ctx.lines.len().saturating_sub(1) )
         )
         )
 }
@@ -236,18 +236,35 @@ fn search<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, Range> {
         tag_s!("/") >>
         pattern: is_not_s!("/") >>
         tag_s!("/") >>
-        ({
-            let re = Regex::new(pattern).unwrap();
-            let mut matching = HashSet::new();
-            for (i, line) in ctx.lines.iter().enumerate() {
-                if re.find(&line).is_some() {
-                    matching.insert(i);
-                }
-            }
-            Range { lines: matching }
-        })
+        range: expr_opt!(search_lines(pattern, ctx)) >>
+        ( range )
         )
 }
+
+// Patterns containing `\n` are matched against the whole buffer, selecting every line a match touches.
+// Returns None if the pattern isn't a valid regex
+fn search_lines(pattern: &str, ctx: &RedBuffer) -> Option<Range> {
+    let mut matching = HashSet::new();
+    if pattern.contains("\\n") {
+        let re = RegexBuilder::new(pattern).multi_line(true).build().ok()?;
+        let text = ctx.lines.join("\n");
+        let mut line = 0;
+        let mut pos = 0;
+        for m in re.find_iter(&text) {
+            line += text[pos..m.start()].matches('\n').count();
+            pos = m.start();
+            matching.extend(line..line + spanned_lines(m.as_str()));
+        }
+    } else {
+        let re = Regex::new(pattern).ok()?;
+        for (i, line) in ctx.lines.iter().enumerate() {
+            if re.find(&line).is_some() {
+                matching.insert(i);
+            }
+        }
+    }
+    Some(Range { lines: matching })
+}
 fn mark<'a>(inp: &'a str, ctx: &RedBuffer) -> IResult<&'a str, Range> {
     do_parse!(
         inp,
@@ -0,0 +1,181 @@
+use nom::IResult;
+use regex::Regex;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+use termion::{color, style};
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+use config;
+use range::Range;
+use range::parse::parse_range;
+use super::{STATE, is_recall};
+
+lazy_static!{
+    static ref MARK: Regex = Regex::new(r"'([^\s']*)$").unwrap();
+    static ref FILE: Regex = Regex::new(r"^(?:e!|e|view!|view|w|bn|source)\s+(.*)$").unwrap();
+    static ref REGISTER: Regex = Regex::new(r"(?:^|\s)(?:pa|d|y|rec|r|@@|@)(\S*)$").unwrap();
+    static ref BUFFER: Regex = Regex::new(r"^bc\s*(\S*)$").unwrap();
+}
+
+// Completes file names, registers, marks and buffers at the prompt, and shows what the
+// range being typed selects
+pub struct RedHelper;
+
+impl Completer for RedHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context) -> Result<(usize, Vec<Pair>)> {
+        let state = STATE.lock().unwrap();
+        let line = &line[..pos];
+
+        // Whatever comes after the range is the action being typed. If the range doesn't parse
+        // or is all there is, the mark being typed (if any) is what needs completing
+        let action = match parse_range(line, &state.buffer) {
+            IResult::Done(rest, _) if !rest.is_empty() => rest,
+            _ => {
+                return Ok(match MARK.captures(line) {
+                    Some(caps) => {
+                        let names = state.buffer.marks.keys().map(|m| m.to_string()).collect();
+                        complete_name(names, caps.get(1).unwrap().start(), &caps[1])
+                    }
+                    None => (pos, vec![]),
+                });
+            }
+        };
+        let offset = pos - action.len();
+
+        if let Some(caps) = FILE.captures(action) {
+            let arg = caps.get(1).unwrap();
+            let (start, files) = complete_path(arg.as_str());
+            return Ok((offset + arg.start() + start, files));
+        }
+        if let Some(caps) = BUFFER.captures(action) {
+            let prefix = caps.get(1).unwrap();
+            // Buffers can be given by ID or by file name
+            let ids = state.buffers.iter()
+                .map(|&(id, ref name)| Pair { display: format!("{} {}", id, name), replacement: id.to_string() });
+            let names = state.buffers.iter()
+                .filter(|&&(_, ref name)| name != "[untitled]")
+                .map(|&(_, ref name)| Pair { display: name.clone(), replacement: name.clone() });
+            let buffers = ids.chain(names)
+                .filter(|pair| pair.replacement.starts_with(prefix.as_str()))
+                .collect();
+            return Ok((offset + prefix.start(), buffers));
+        }
+        if let Some(caps) = REGISTER.captures(action) {
+            let prefix = caps.get(1).unwrap();
+            let (start, regs) = complete_name(state.registers.clone(), prefix.start(), prefix.as_str());
+            return Ok((offset + start, regs));
+        }
+        Ok((pos, vec![]))
+    }
+}
+
+impl Hinter for RedHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context) -> Option<String> {
+        if pos < line.len() || line.trim().is_empty() || is_recall(line) || !config::CONF.lock().unwrap().preview {
+            return None;
+        }
+        let state = STATE.lock().unwrap();
+
+        let (rest, range) = match parse_range(line, &state.buffer) {
+            IResult::Done(rest, range) => (rest, range),
+            _ => (line, Range::empty()),
+        };
+        // Actions can't start with any of these, so whatever is left of the range couldn't be parsed
+        if rest.starts_with(|c: char| c.is_ascii_digit() || "/'()!*+#^&$%.-".contains(c)) {
+            return Some(format!("  ({})", range_error(rest)));
+        }
+        if rest.len() == line.len() {
+            return None;
+        }
+
+        let conf = config::CONF.lock().unwrap();
+        let first = range.lines.iter().min().map(|&l| conf.format_number(l));
+        let last = range.lines.iter().max().map(|&l| conf.format_number(l));
+        Some(match (range.lines.len(), first, last) {
+            (1, Some(first), _) => format!("  (1 line, {})", first),
+            (n, Some(first), Some(last)) => format!("  ({} lines, {}-{})", n, first, last),
+            _ => "  (no lines)".into(),
+        })
+    }
+}
+
+impl Highlighter for RedHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if config::CONF.lock().unwrap().colors {
+            Cow::Owned(format!("{}{}{}", color::Fg(color::LightBlack), hint, style::Reset))
+        } else {
+            Cow::Borrowed(hint)
+        }
+    }
+}
+
+impl Validator for RedHelper { }
+
+impl Helper for RedHelper { }
+
+// Explains why the range at the start of rest couldn't be parsed
+fn range_error(rest: &str) -> String {
+    // The range after an operator is usually the one at fault
+    let inner = rest.trim_start_matches(|c| "*+!".contains(c));
+    if inner.starts_with('/') {
+        match inner[1..].find('/') {
+            Some(end) => match Regex::new(&inner[1..end + 1]) {
+                // The last line of a regex error says what's wrong, the ones before show where
+                Err(e) => format!("Invalid regex: {}", e.to_string().lines().last().unwrap_or("").trim_start_matches("error: ")),
+                Ok(_) => format!("Invalid range at {}", rest),
+            },
+            None => "Unterminated regex".into(),
+        }
+    } else if inner.starts_with('(') {
+        "Unclosed parenthesis".into()
+    } else {
+        format!("Invalid range at {}", rest)
+    }
+}
+
+fn complete_name(mut names: Vec<String>, start: usize, prefix: &str) -> (usize, Vec<Pair>) {
+    names.sort();
+    let pairs = names.into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Pair { display: name.clone(), replacement: name })
+        .collect();
+    (start, pairs)
+}
+
+// Lists the entries of the directory in path starting with its last component. Returns the
+// position of that component in path and the candidates, with a / after directories
+fn complete_path(path: &str) -> (usize, Vec<Pair>) {
+    let start = path.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let (dir, prefix) = path.split_at(start);
+    let dir = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+
+    let mut pairs: Vec<Pair> = match fs::read_dir(dir) {
+        Ok(entries) => {
+            entries.filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let mut name = entry.file_name().into_string().ok()?;
+                    if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                        return None;
+                    }
+                    if entry.path().is_dir() {
+                        name.push('/');
+                    }
+                    Some(Pair { display: name.clone(), replacement: name })
+                })
+                .collect()
+        }
+        Err(_) => vec![],
+    };
+    pairs.sort_by(|a, b| a.display.cmp(&b.display));
+    (start, pairs)
+}
@@ -1,21 +1,58 @@
 
+mod helper;
+
 use rustyline::Editor;
 use rustyline::error::ReadlineError;
+use std::collections::HashSet;
+use std::fs;
 use std::sync::Mutex;
 
 use config;
+use red_buffer::RedBuffer;
+use red_master::RedMaster;
+use self::helper::RedHelper;
 
 lazy_static!{
-    static ref EDITOR: Mutex<Editor<()>> = Mutex::new(Editor::new());
+    static ref EDITOR: Mutex<Editor<RedHelper>> = {
+        let mut editor = Editor::new();
+        editor.set_helper(Some(RedHelper));
+        Mutex::new(editor)
+    };
     static ref BACKLOG: Mutex<Option<Vec<String>>> = Mutex::new(None);
+    // Lines to read before anything else, eg. from replaying a macro, and whether each one was
+    // split off a line by `;`
+    static ref QUEUE: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
+    static ref RECORDING: Mutex<Option<Vec<String>>> = Mutex::new(None);
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        buffer: RedBuffer::empty(),
+        buffers: Vec::new(),
+        registers: Vec::new(),
+    });
+}
+
+// What the prompt knows about the editor, for completing input
+struct State {
+    buffer: RedBuffer, // A copy of the current buffer
+    buffers: Vec<(usize, String)>, // IDs and names, the most recently used first
+    registers: Vec<String>,
 }
 
 
 pub fn read_line(prompt: &str) -> Result<String, ReadlineError> {
+    read_line_initial(prompt, "")
+}
+
+// Like read_line, but the line starts out containing initial, which the user can edit
+pub fn read_line_initial(prompt: &str, initial: &str) -> Result<String, ReadlineError> {
     let mut backlog = BACKLOG.lock().unwrap();
+    let mut queue = QUEUE.lock().unwrap();
     let silent = config::CONF.lock().unwrap().silent;
 
-    if let Some(ref mut backlog) = *backlog {
+    if !queue.is_empty() {
+        return Ok(queue.remove(0).0);
+    }
+
+    let line = if let Some(ref mut backlog) = *backlog {
         if backlog.is_empty() {
             Err(ReadlineError::Eof)
         } else {
@@ -27,17 +64,156 @@ pub fn read_line(prompt: &str) -> Result<String, ReadlineError> {
             Ok(line)
         }
     } else {
-        EDITOR.lock().unwrap().readline(prompt)
+        EDITOR.lock().unwrap().readline_with_initial(prompt, (initial, ""))
+    };
+
+    if let (&Ok(ref line), &mut Some(ref mut recorded)) = (&line, &mut *RECORDING.lock().unwrap()) {
+        recorded.push(line.clone());
     }
+    line
+}
 
+pub fn is_interactive() -> bool {
+    BACKLOG.lock().unwrap().is_none()
 }
 
-pub fn add_command(cmd: String) {
-    let mut backlog = BACKLOG.lock().unwrap();
-    if backlog.is_none() {
-        *backlog = Some(Vec::new());
+// Whether the next line read can be edited by the user before it's used. That's the case for
+// what the user types, and for the lines recorded that way in macros. The commands after a `;`
+// and the ones from -d are given up front instead
+pub fn can_prefill() -> bool {
+    is_interactive() && !QUEUE.lock().unwrap().first().map(|&(_, part)| part).unwrap_or(false)
+}
+
+// Makes the following lines be read instead of the user's input, until they run out
+pub fn set_commands(cmds: Vec<String>) {
+    *BACKLOG.lock().unwrap() = Some(cmds);
+}
+
+// Makes the following calls to read_line return these lines, before any other input
+pub fn queue_commands(cmds: Vec<String>) {
+    queue(cmds, false);
+}
+
+// Like queue_commands, for the commands split off a line
+pub fn queue_parts(cmds: Vec<String>) {
+    queue(cmds, true);
+}
+
+fn queue(cmds: Vec<String>, parts: bool) {
+    let mut queue = QUEUE.lock().unwrap();
+    for (i, cmd) in cmds.into_iter().enumerate() {
+        queue.insert(i, (cmd, parts));
+    }
+}
+
+pub fn queued() -> usize {
+    QUEUE.lock().unwrap().len()
+}
+
+pub fn clear_queue(keep: usize) {
+    let mut queue = QUEUE.lock().unwrap();
+    let len = queue.len();
+    queue.drain(..len.saturating_sub(keep));
+}
+
+// Starts saving every line read, except for the queued ones
+pub fn start_recording() {
+    *RECORDING.lock().unwrap() = Some(Vec::new());
+}
+
+pub fn stop_recording() -> Option<Vec<String>> {
+    RECORDING.lock().unwrap().take()
+}
+
+// Loads the history saved by earlier sessions
+pub fn load_history() {
+    let path = match config::history_file() {
+        Some(path) => path,
+        None => return,
+    };
+    let mut editor = EDITOR.lock().unwrap();
+    editor.history_mut().set_max_len(config::CONF.lock().unwrap().history);
+    if editor.load_history(&path).is_ok() {
+        dedup_history(&mut editor);
+    }
+}
+
+// Saves the history for later sessions. Failing to do so isn't worth interrupting the user over
+pub fn save_history() {
+    if let Some(path) = config::history_file() {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = EDITOR.lock().unwrap().save_history(&path);
+    }
+}
+
+// Adds a line to the end of the history, removing any earlier copy of it
+pub fn add_history(line: &str) {
+    let mut editor = EDITOR.lock().unwrap();
+    editor.history_mut().set_max_len(config::CONF.lock().unwrap().history);
+    editor.add_history_entry(line);
+    dedup_history(&mut editor);
+}
+
+pub fn history() -> Vec<String> {
+    EDITOR.lock().unwrap().history().iter().cloned().collect()
+}
+
+// Expands `!` to the last command, `!N` to the N-th command in the history and `!prefix` to the
+// last command starting with prefix. `!` followed by anything else is an inverted range, and
+// other lines are returned as they are
+pub fn expand_history(line: &str) -> Result<String, String> {
+    if !is_recall(line) {
+        return Ok(line.to_string());
     }
-    if let Some(ref mut bl) = *backlog {
-        bl.push(cmd);
+    let arg = &line.trim()[1..];
+    let history = history();
+
+    if arg.is_empty() {
+        history.last().cloned().ok_or("The history is empty".into())
+    } else if arg.chars().all(|c| c.is_ascii_digit()) {
+        let n: usize = arg.parse().map_err(|_| format!("No command {} in the history", arg))?;
+        history.get(n.wrapping_sub(1)).cloned().ok_or(format!("No command {} in the history", arg))
+    } else {
+        history.iter().rev()
+            .find(|cmd| cmd.starts_with(arg))
+            .cloned()
+            .ok_or(format!("No command starting with {} in the history", arg))
     }
-}
\ No newline at end of file
+}
+
+pub fn is_recall(line: &str) -> bool {
+    let trimmed = line.trim();
+    if !trimmed.starts_with('!') {
+        return false;
+    }
+    let arg = &trimmed[1..];
+    arg.chars().all(|c| c.is_ascii_digit()) || arg.starts_with(|c: char| c.is_alphabetic())
+}
+
+fn dedup_history(editor: &mut Editor<RedHelper>) {
+    let mut seen = HashSet::new();
+    let mut unique: Vec<String> = editor.history().iter().rev()
+        .filter(|entry| seen.insert(entry.to_string()))
+        .cloned()
+        .collect();
+    if unique.len() != editor.history().len() {
+        unique.reverse();
+        editor.clear_history();
+        for entry in unique {
+            editor.add_history_entry(entry);
+        }
+    }
+}
+
+// Should be called before reading a line, so the completions match the current state
+pub fn update_state(master: &RedMaster) {
+    let mut state = STATE.lock().unwrap();
+    state.buffer = master.curr_buf().clone();
+    state.buffers = master.recent_buffers().into_iter()
+        .map(|i| &master.buffers[i])
+        .map(|buf| (buf.id, buf.filename.clone().unwrap_or("[untitled]".into())))
+        .collect();
+    state.registers = master.registers.keys().map(|r| r.to_string()).collect();
+}
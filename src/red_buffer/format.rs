@@ -0,0 +1,167 @@
+use std::io;
+
+// How a buffer is stored on disk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileFormat {
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    pub trim_trailing_whitespace: bool,
+    pub charset: Charset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charset {
+    Utf8,
+    Utf8Bom,
+    Latin1,
+    Utf16Be,
+    Utf16Le,
+}
+
+static BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+impl FileFormat {
+    pub fn new() -> FileFormat {
+        FileFormat {
+            line_ending: LineEnding::Lf,
+            final_newline: false,
+            trim_trailing_whitespace: false,
+            charset: Charset::Utf8,
+        }
+    }
+
+    // Decodes the content of a file, remembering the line ending and final newline it uses
+    pub fn read(&mut self, bytes: &[u8]) -> io::Result<Vec<String>> {
+        if self.charset == Charset::Utf8 && bytes.starts_with(BOM) {
+            self.charset = Charset::Utf8Bom;
+        }
+        let content = self.charset.decode(bytes)?;
+
+        self.line_ending =
+            if content.contains("\r\n") {
+                LineEnding::CrLf
+            } else if content.contains('\r') && !content.contains('\n') {
+                LineEnding::Cr
+            } else {
+                LineEnding::Lf
+            };
+        // The detected ending is only used for writing, so a file mixing them still splits on every line
+        let end = if self.line_ending == LineEnding::Cr { '\r' } else { '\n' };
+        self.final_newline = content.ends_with(end);
+
+        if content.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut lines: Vec<String> = content.split(end)
+            .map(|x| x.trim_end_matches('\r').to_string())
+            .collect();
+        if self.final_newline {
+            lines.pop();
+        }
+        Ok(lines)
+    }
+
+    pub fn write(&self, lines: &[String]) -> io::Result<Vec<u8>> {
+        let mut content = lines.join(self.line_ending.as_str());
+        if self.final_newline && !lines.is_empty() {
+            content.push_str(self.line_ending.as_str());
+        }
+        self.charset.encode(&content)
+    }
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+impl Charset {
+    pub fn decode(&self, bytes: &[u8]) -> io::Result<String> {
+        match *self {
+            Charset::Utf8 | Charset::Utf8Bom => {
+                let bytes = if bytes.starts_with(BOM) { &bytes[BOM.len()..] } else { bytes };
+                String::from_utf8(bytes.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Charset::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+            Charset::Utf16Be | Charset::Utf16Le => {
+                let units: Vec<u16> = bytes.chunks(2)
+                    .map(|c| {
+                        let (a, b) = (c[0] as u16, *c.get(1).unwrap_or(&0) as u16);
+                        if *self == Charset::Utf16Be { a << 8 | b } else { b << 8 | a }
+                    })
+                    .skip_while(|&u| u == 0xFEFF)
+                    .collect();
+                String::from_utf16(&units).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+        match *self {
+            Charset::Utf8 => Ok(text.as_bytes().to_vec()),
+            Charset::Utf8Bom => Ok(BOM.iter().chain(text.as_bytes()).cloned().collect()),
+            Charset::Latin1 => {
+                text.chars()
+                    .map(|c| if (c as u32) < 0x100 {
+                        Ok(c as u8)
+                    } else {
+                        Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} can't be written as latin1", c)))
+                    })
+                    .collect()
+            }
+            Charset::Utf16Be | Charset::Utf16Le => {
+                Ok(text.encode_utf16()
+                    .flat_map(|u| {
+                        let (hi, lo) = ((u >> 8) as u8, u as u8);
+                        if *self == Charset::Utf16Be { vec![hi, lo] } else { vec![lo, hi] }
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileFormat, LineEnding};
+
+    fn read(content: &str) -> (FileFormat, Vec<String>) {
+        let mut format = FileFormat::new();
+        let lines = format.read(content.as_bytes()).unwrap();
+        (format, lines)
+    }
+
+    #[test]
+    fn line_endings() {
+        let (format, lines) = read("a\r\nb\r\n");
+        assert_eq!(lines, vec!["a", "b"]);
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert!(format.final_newline);
+
+        let (format, lines) = read("a\rb");
+        assert_eq!(lines, vec!["a", "b"]);
+        assert_eq!(format.line_ending, LineEnding::Cr);
+        assert!(!format.final_newline);
+    }
+
+    #[test]
+    fn mixed_line_endings() {
+        let (format, lines) = read("a\r\nb\nc\n");
+        assert_eq!(lines, vec!["a", "b", "c"]);
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert!(format.final_newline);
+        assert_eq!(format.write(&lines).unwrap(), b"a\r\nb\r\nc\r\n".to_vec());
+    }
+}
@@ -1,15 +1,31 @@
+pub mod format;
+
 use range::Range;
 use action::ActionErr;
 use std::collections::HashMap;
+use std::ops::Deref;
+
+use config;
+use self::format::FileFormat;
 
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RedBuffer {
+    pub id: usize, // Set by the RedMaster, doesn't change when other buffers are closed
     pub lines: Vec<String>,
     pub cursor: Range,
     pub marks: HashMap<Mark, Range>,
     pub filename: Option<String>,
-    pub saved: bool
+    pub saved: bool,
+    pub read_only: bool, // Refuse to write the buffer
+    pub indent: Option<Indent>, // How the file is indented, if known
+    pub format: FileFormat,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -25,6 +41,14 @@ impl From<String> for Mark {
     }
 }
 
+impl Deref for Mark {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
 impl RedBuffer {
     pub fn insert_line(&mut self, at: usize, line: String) -> Result<(), ActionErr>{
         if at > self.lines.len() {
@@ -37,22 +61,102 @@ impl RedBuffer {
         Ok(())
     }
     pub fn delete_line(&mut self, at: usize) -> Result<(), ActionErr>{
-        if at > self.lines.len() {
+        if at >= self.lines.len() {
             return Err(ActionErr::OutOfBounds);
         }
         self.lines.remove(at);
         self.cursor = self.clone().cursor.removed_line(at);
-        self.marks = self.marks.clone().into_iter().map(|(m, r)| (m, r.removed_line(at))).collect();
+        self.marks = self.marks.clone().into_iter().map(|(m, r)| (m, r.deleted_line(at))).collect();
         self.saved = false;
         Ok(())
     }
+    // Replaces the lines start..end with new_lines, inserting or deleting lines at the end as needed
+    pub fn replace_lines(&mut self, start: usize, end: usize, new_lines: Vec<String>) -> Result<(), ActionErr>{
+        if start > end || end > self.lines.len() {
+            return Err(ActionErr::OutOfBounds);
+        }
+        let old_len = end - start;
+        let new_len = new_lines.len();
+        for (i, line) in new_lines.into_iter().enumerate() {
+            if i < old_len {
+                self.lines[start + i] = line;
+            } else {
+                self.insert_line(start + i, line)?;
+            }
+        }
+        for _ in new_len..old_len {
+            self.delete_line(start + new_len)?;
+        }
+        self.saved = false;
+        Ok(())
+    }
+    // Guesses how the buffer is indented from the lines that are. Tabs are used if most indented
+    // lines start with one, otherwise the most common change in indentation between lines
+    pub fn detect_indent(&mut self) {
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        let mut steps: HashMap<usize, usize> = HashMap::new();
+        let mut last_depth = 0;
+
+        for line in self.lines.iter().filter(|l| !l.trim().is_empty()) {
+            if line.starts_with('\t') {
+                tab_lines += 1;
+                continue;
+            }
+            let depth = line.find(|c| c != ' ').unwrap_or(0);
+            if depth > 0 {
+                space_lines += 1;
+            }
+            if depth != last_depth {
+                let step = if depth > last_depth { depth - last_depth } else { last_depth - depth };
+                *steps.entry(step).or_insert(0) += 1;
+            }
+            last_depth = depth;
+        }
+
+        self.indent =
+            if tab_lines == 0 && space_lines == 0 {
+                None
+            } else if tab_lines > space_lines {
+                Some(Indent::Tabs)
+            } else {
+                steps.into_iter()
+                    .max_by_key(|&(step, count)| (count, step))
+                    .map(|(step, _)| Indent::Spaces(step))
+            };
+    }
+
+    // The string to indent a line one level with, following the buffer's indentation if known
+    pub fn indent_unit(&self) -> String {
+        match self.indent {
+            Some(Indent::Tabs) => "\t".into(),
+            Some(Indent::Spaces(n)) => " ".repeat(n),
+            None => config::CONF.lock().unwrap().indent_unit(),
+        }
+    }
+
+    // The number of columns one level of indentation takes
+    pub fn indent_width(&self) -> usize {
+        let conf = config::CONF.lock().unwrap();
+        match self.indent {
+            Some(Indent::Tabs) => conf.tab_width,
+            Some(Indent::Spaces(n)) => n,
+            None if conf.tabs => conf.tab_width,
+            None => conf.indent_width,
+        }
+    }
+
     pub fn empty() -> RedBuffer {
         RedBuffer {
+            id: 0,
             lines: vec![ "".into() ],
             cursor: Range::empty(),
             marks: HashMap::new(),
             filename: None,
-            saved: true
+            saved: true,
+            read_only: false,
+            indent: None,
+            format: FileFormat::new(),
         }
     }
 }
@@ -9,7 +9,10 @@ use action::ActionErr;
 pub struct RedMaster {
     pub buffers: Vec<RedBuffer>,
     current_buffer: usize,
-    pub registers: HashMap<Register, Vec<String>>
+    next_id: usize,
+    recent: Vec<usize>, // The IDs of the buffers, the most recently used first
+    pub registers: HashMap<Register, Vec<String>>,
+    pub recording: Option<Register>, // The register a macro is being recorded into
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -35,7 +38,14 @@ impl Deref for Register {
 
 impl RedMaster {
     pub fn empty() -> RedMaster {
-        RedMaster { buffers: vec![ RedBuffer::empty() ], current_buffer: 0, registers: HashMap::new() }
+        RedMaster {
+            buffers: vec![ RedBuffer::empty() ],
+            current_buffer: 0,
+            next_id: 1,
+            recent: vec![0],
+            registers: HashMap::new(),
+            recording: None,
+        }
     }
 
     pub fn curr_buf(&self) -> &RedBuffer {
@@ -59,4 +69,56 @@ impl RedMaster {
         }
     }
 
+    // Like change_buffer, but the buffer counts as used. Use change_buffer for switching
+    // temporarily, like when doing something in every buffer
+    pub fn switch_buffer(&mut self, idx: usize) -> Result<(), ActionErr> {
+        self.change_buffer(idx)?;
+        let id = self.buffers[idx].id;
+        self.recent.retain(|&r| r != id);
+        self.recent.insert(0, id);
+        Ok(())
+    }
+
+    // Adds a buffer with an ID of its own, and switches to it
+    pub fn add_buffer(&mut self, mut buf: RedBuffer) -> Result<(), ActionErr> {
+        buf.id = self.next_id;
+        self.next_id += 1;
+        self.buffers.push(buf);
+        let idx = self.buffers.len() - 1;
+        self.switch_buffer(idx)
+    }
+
+    // Removes the current buffer, switching to the one used before it
+    pub fn remove_buffer(&mut self) -> Result<(), ActionErr> {
+        let removed = self.buffers.remove(self.current_buffer);
+        self.recent.retain(|&r| r != removed.id);
+        let idx = self.recent.first()
+            .and_then(|&id| self.find_buffer(id))
+            .ok_or(ActionErr::OutOfBounds)?;
+        self.change_buffer(idx)
+    }
+
+    // The index of the buffer with this ID
+    pub fn find_buffer(&self, id: usize) -> Option<usize> {
+        self.buffers.iter().position(|buf| buf.id == id)
+    }
+
+    // The buffer used before the current one
+    pub fn alternate_buffer(&self) -> Option<usize> {
+        self.recent.get(1).and_then(|&id| self.find_buffer(id))
+    }
+
+    // The indices of the buffers, the most recently used first
+    pub fn recent_buffers(&self) -> Vec<usize> {
+        self.recent.iter().filter_map(|&id| self.find_buffer(id)).collect()
+    }
+
+    // The names of the buffers with changes that haven't been written
+    pub fn unsaved_buffers(&self) -> Vec<String> {
+        self.buffers.iter()
+            .filter(|buf| !buf.saved)
+            .map(|buf| buf.filename.clone().unwrap_or("[untitled]".into()))
+            .collect()
+    }
+
 }